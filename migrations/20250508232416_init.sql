@@ -4,11 +4,11 @@ create table beatmapsets (
   artist text not null,
   title text not null,
   creator text not null,
-  creator_id int not null,
+  creator_id int not null
 );
 
 create table beatmaps (
   id integer primary key,
-  beatmapset_id integer,
+  beatmapset_id integer not null references beatmapsets (id) on delete cascade,
   version text not null
 );
\ No newline at end of file
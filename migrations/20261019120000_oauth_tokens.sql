@@ -0,0 +1,13 @@
+create table oauth_tokens (
+  id integer primary key,
+  user_id integer references users (id) on delete cascade,
+  access_token text not null unique,
+  refresh_token text unique,
+  scopes text not null,
+  created_at datetime not null,
+  expires_at datetime not null,
+  refresh_expires_at datetime not null,
+  revoked boolean not null default false
+);
+
+create index oauth_tokens_user_id on oauth_tokens (user_id);
\ No newline at end of file
@@ -0,0 +1,7 @@
+create table users (
+  id integer primary key,
+  username text not null unique collate nocase,
+  email text unique collate nocase,
+  password_hash text not null,
+  joined_at datetime not null
+);
\ No newline at end of file
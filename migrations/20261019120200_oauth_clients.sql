@@ -0,0 +1,15 @@
+create table oauth_clients (
+  id integer primary key,
+  name text not null,
+  -- sha256 of the secret, hex encoded
+  secret_hash text not null,
+  scopes text not null,
+  created_at datetime not null default current_timestamp
+);
+
+-- client credentials baked into the client's DevelopmentEndpointConfiguration
+-- the secret is 3LP2mhUrV89xxzD1YKNndXHEhWWCRLPNKioZ9ymT
+insert into oauth_clients (id, name, secret_hash, scopes) values
+(5, 'osu!lazer', '089e8378c60e6abac41dfbe000bed9f5cfc89ccec6ecfe6a0523af5f15da93bb', '*');
+
+alter table oauth_tokens add column client_id integer references oauth_clients (id) on delete cascade;
\ No newline at end of file
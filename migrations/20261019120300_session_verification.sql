@@ -0,0 +1,4 @@
+alter table oauth_tokens add column verified boolean not null default false;
+alter table oauth_tokens add column verification_code text;
+alter table oauth_tokens add column verification_sent_at datetime;
+alter table oauth_tokens add column verification_attempts integer not null default 0;
\ No newline at end of file
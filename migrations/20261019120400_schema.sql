@@ -0,0 +1,135 @@
+alter table users add column country_code text not null default 'XX';
+
+create table statistics (
+  user_id integer not null references users (id) on delete cascade,
+  ruleset_id integer not null,
+  level real not null default 1,
+  pp real not null default 0,
+  accuracy real not null default 0,
+  playcount integer not null default 0,
+  play_time integer not null default 0,
+  ranked_score integer not null default 0,
+  total_score integer not null default 0,
+  total_hits integer not null default 0,
+  maximum_combo integer not null default 0,
+  replays_watched_by_others integer not null default 0,
+  ssh integer not null default 0,
+  ss integer not null default 0,
+  sh integer not null default 0,
+  s integer not null default 0,
+  a integer not null default 0,
+  primary key (user_id, ruleset_id)
+);
+
+create index statistics_ruleset_pp on statistics (ruleset_id, pp desc);
+
+-- beatmapsets
+
+alter table beatmapsets add column artist_unicode text;
+alter table beatmapsets add column title_unicode text;
+alter table beatmapsets add column source text not null default '';
+alter table beatmapsets add column tags text not null default '';
+-- -2 graveyard, -1 wip, 0 pending, 1 ranked, 2 approved, 3 qualified, 4 loved
+alter table beatmapsets add column status integer not null default 0;
+alter table beatmapsets add column genre_id integer not null default 1;
+alter table beatmapsets add column language_id integer not null default 1;
+alter table beatmapsets add column has_video boolean not null default false;
+alter table beatmapsets add column has_storyboard boolean not null default false;
+alter table beatmapsets add column submitted_at datetime;
+alter table beatmapsets add column ranked_at datetime;
+alter table beatmapsets add column updated_at datetime;
+
+create index beatmapsets_creator_id on beatmapsets (creator_id);
+
+-- beatmaps
+
+alter table beatmaps add column checksum text;
+alter table beatmaps add column filename text;
+alter table beatmaps add column ruleset_id integer not null default 0;
+alter table beatmaps add column status integer not null default 0;
+alter table beatmaps add column difficulty_rating real not null default 0;
+alter table beatmaps add column drain real not null default 5;
+alter table beatmaps add column cs real not null default 5;
+alter table beatmaps add column od real not null default 5;
+alter table beatmaps add column ar real not null default 5;
+alter table beatmaps add column bpm real not null default 0;
+alter table beatmaps add column total_length integer not null default 0;
+alter table beatmaps add column hit_length integer not null default 0;
+alter table beatmaps add column max_combo integer not null default 0;
+alter table beatmaps add column count_circles integer not null default 0;
+alter table beatmaps add column count_sliders integer not null default 0;
+alter table beatmaps add column count_spinners integer not null default 0;
+alter table beatmaps add column updated_at datetime;
+
+create unique index beatmaps_checksum on beatmaps (checksum);
+create index beatmaps_beatmapset_id on beatmaps (beatmapset_id);
+
+-- scores
+
+create table scores (
+  id integer primary key,
+  user_id integer not null references users (id) on delete cascade,
+  beatmap_id integer not null references beatmaps (id) on delete cascade,
+  ruleset_id integer not null,
+  passed boolean not null,
+  rank text not null,
+  total_score integer not null,
+  accuracy real not null,
+  max_combo integer not null,
+  pp real,
+  -- json encoded, in the client's SoloScoreInfo format
+  mods text not null default '[]',
+  statistics text not null default '{}',
+  maximum_statistics text not null default '{}',
+  started_at datetime,
+  ended_at datetime not null
+);
+
+create index scores_beatmap on scores (beatmap_id, ruleset_id, total_score desc);
+create index scores_user on scores (user_id, ruleset_id, ended_at desc);
+
+-- relations
+
+create table relations (
+  user_id integer not null references users (id) on delete cascade,
+  target_id integer not null references users (id) on delete cascade,
+  -- 0 friend, 1 block
+  relation_type integer not null,
+  created_at datetime not null default current_timestamp,
+  primary key (user_id, target_id)
+);
+
+create index relations_target_id on relations (target_id);
+
+-- chat
+
+create table channels (
+  id integer primary key,
+  name text not null,
+  description text not null default '',
+  -- PUBLIC, PM, ...
+  type text not null,
+  moderated boolean not null default false,
+  created_at datetime not null default current_timestamp
+);
+
+create table channel_members (
+  channel_id integer not null references channels (id) on delete cascade,
+  user_id integer not null references users (id) on delete cascade,
+  last_read_id integer,
+  joined_at datetime not null default current_timestamp,
+  primary key (channel_id, user_id)
+);
+
+create index channel_members_user_id on channel_members (user_id);
+
+create table messages (
+  id integer primary key,
+  channel_id integer not null references channels (id) on delete cascade,
+  sender_id integer not null references users (id) on delete cascade,
+  content text not null,
+  is_action boolean not null default false,
+  created_at datetime not null
+);
+
+create index messages_channel_id on messages (channel_id, id);
\ No newline at end of file
@@ -0,0 +1,11 @@
+-- the original .osz, kept so downloads don't have to rebuild it
+alter table beatmapsets add column archive_hash text;
+
+-- every file inside a beatmapset's archive, pointing into content-addressed storage
+create table beatmapset_files (
+  beatmapset_id integer not null references beatmapsets (id) on delete cascade,
+  filename text not null,
+  hash text not null,
+  size integer not null,
+  primary key (beatmapset_id, filename)
+);
\ No newline at end of file
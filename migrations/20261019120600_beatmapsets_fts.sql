@@ -0,0 +1,32 @@
+-- full text index over the searchable beatmapset metadata, kept in sync by the triggers below
+create virtual table beatmapsets_fts using fts5 (
+  artist,
+  artist_unicode,
+  title,
+  title_unicode,
+  creator,
+  source,
+  tags,
+  content = 'beatmapsets',
+  content_rowid = 'id',
+  tokenize = 'unicode61 remove_diacritics 2'
+);
+
+insert into beatmapsets_fts (beatmapsets_fts) values ('rebuild');
+
+create trigger beatmapsets_fts_insert after insert on beatmapsets begin
+  insert into beatmapsets_fts (rowid, artist, artist_unicode, title, title_unicode, creator, source, tags)
+  values (new.id, new.artist, new.artist_unicode, new.title, new.title_unicode, new.creator, new.source, new.tags);
+end;
+
+create trigger beatmapsets_fts_delete after delete on beatmapsets begin
+  insert into beatmapsets_fts (beatmapsets_fts, rowid, artist, artist_unicode, title, title_unicode, creator, source, tags)
+  values ('delete', old.id, old.artist, old.artist_unicode, old.title, old.title_unicode, old.creator, old.source, old.tags);
+end;
+
+create trigger beatmapsets_fts_update after update of artist, artist_unicode, title, title_unicode, creator, source, tags on beatmapsets begin
+  insert into beatmapsets_fts (beatmapsets_fts, rowid, artist, artist_unicode, title, title_unicode, creator, source, tags)
+  values ('delete', old.id, old.artist, old.artist_unicode, old.title, old.title_unicode, old.creator, old.source, old.tags);
+  insert into beatmapsets_fts (rowid, artist, artist_unicode, title, title_unicode, creator, source, tags)
+  values (new.id, new.artist, new.artist_unicode, new.title, new.title_unicode, new.creator, new.source, new.tags);
+end;
\ No newline at end of file
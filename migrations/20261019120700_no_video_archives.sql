@@ -0,0 +1,2 @@
+-- archive rebuilt without video files, built on the first download that asks for it
+alter table beatmapsets add column no_video_archive_hash text;
\ No newline at end of file
@@ -0,0 +1,13 @@
+-- handed out when a play starts, a score can only be submitted against an unused token
+create table score_tokens (
+  id integer primary key,
+  user_id integer not null references users (id) on delete cascade,
+  beatmap_id integer not null references beatmaps (id) on delete cascade,
+  ruleset_id integer not null,
+  beatmap_hash text not null,
+  version_hash text not null,
+  score_id integer references scores (id) on delete set null,
+  created_at datetime not null
+);
+
+create index score_tokens_user_id on score_tokens (user_id);
\ No newline at end of file
@@ -0,0 +1,4 @@
+-- what the client submitted, accuracy and rank are recomputed by the server and the total score is checked against them
+alter table scores add column reported_total_score integer;
+alter table scores add column reported_accuracy real;
+alter table scores add column reported_rank text;
\ No newline at end of file
@@ -0,0 +1,8 @@
+alter table scores add column has_replay boolean not null default false;
+
+-- who downloaded a replay, so each user only counts once towards replays_watched_by_others
+create table replay_views (
+  score_id integer not null references scores (id) on delete cascade,
+  user_id integer not null references users (id) on delete cascade,
+  primary key (score_id, user_id)
+);
\ No newline at end of file
@@ -0,0 +1,9 @@
+create table score_pins (
+  score_id integer primary key references scores (id) on delete cascade,
+  user_id integer not null references users (id) on delete cascade,
+  -- lower comes first, new pins go on top
+  display_order integer not null,
+  created_at datetime not null default current_timestamp
+);
+
+create index score_pins_user on score_pins (user_id, display_order);
\ No newline at end of file
@@ -0,0 +1,39 @@
+alter table users add column is_supporter boolean not null default false;
+alter table users add column is_bot boolean not null default false;
+alter table users add column profile_colour text;
+alter table users add column title text;
+-- json array of "mouse", "keyboard", "tablet" and "touch"
+alter table users add column playstyle text not null default '[]';
+-- hashes of the uploaded images, links to them are built from the configured base url
+alter table users add column avatar_hash text;
+alter table users add column cover_hash text;
+
+create table groups (
+  id integer primary key,
+  identifier text not null unique,
+  name text not null,
+  short_name text not null,
+  colour text
+);
+
+insert into groups (id, identifier, name, short_name, colour) values
+  (1, 'admin', 'Administrators', 'ADM', '#ff5c5c'),
+  (2, 'moderator', 'Moderators', 'MOD', '#99eb47'),
+  (3, 'bot', 'Bots', 'BOT', '#8fb4d6');
+
+create table user_groups (
+  user_id integer not null references users (id) on delete cascade,
+  group_id integer not null references groups (id) on delete cascade,
+  primary key (user_id, group_id)
+);
+
+-- taken once a day, for rank history and monthly playcounts
+create table statistics_snapshots (
+  user_id integer not null references users (id) on delete cascade,
+  ruleset_id integer not null,
+  date date not null,
+  pp real not null,
+  global_rank integer,
+  playcount integer not null,
+  primary key (user_id, ruleset_id, date)
+);
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- only friends can start a conversation with the user
+alter table users add column pm_friends_only boolean not null default false;
\ No newline at end of file
@@ -0,0 +1,6 @@
+-- private channels are looked up by name
+create unique index channels_name on channels (name);
+
+insert into channels (name, description, type) values
+  ('#lobby', 'Looking for a game?', 'PUBLIC'),
+  ('#osu', 'General discussion.', 'PUBLIC');
\ No newline at end of file
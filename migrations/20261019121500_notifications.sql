@@ -0,0 +1,15 @@
+create table notifications (
+  id integer primary key,
+  user_id integer not null references users (id) on delete cascade,
+  -- channel_message, user_friend_new, ...
+  name text not null,
+  object_type text not null,
+  object_id integer not null,
+  source_user_id integer references users (id) on delete set null,
+  -- json, what's shown with the notification
+  details text not null default '{}',
+  is_read boolean not null default false,
+  created_at datetime not null
+);
+
+create index notifications_user_id on notifications (user_id, id);
\ No newline at end of file
@@ -0,0 +1,11 @@
+create table silences (
+  id integer primary key,
+  user_id integer not null references users (id) on delete cascade,
+  -- moderator that silenced the user, null when it was done from the cli
+  actor_id integer references users (id) on delete set null,
+  reason text not null default '',
+  created_at datetime not null,
+  ends_at datetime not null
+);
+
+create index silences_user_id on silences (user_id, ends_at);
\ No newline at end of file
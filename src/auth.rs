@@ -1,56 +1,346 @@
-use axum::{extract::{Request, State}, http::{header, StatusCode}, middleware::Next, response::{IntoResponse, Response}, Extension};
-use sqlx::prelude::FromRow;
+use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use axum::{extract::{Request, State}, http::{header, HeaderMap, StatusCode}, middleware::Next, response::{IntoResponse, Response}, Extension, Json};
+use chrono::{DateTime, Duration, Utc};
+use rand::{distr::Alphanumeric, Rng};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::{prelude::FromRow, Executor, Sqlite, SqlitePool};
+use subtle::ConstantTimeEq;
 
-use crate::state::FiberState;
+use crate::{config::Config, images, state::FiberState};
+
+/// how long an access token stays valid
+pub const ACCESS_TOKEN_LIFETIME: Duration = Duration::days(1);
+
+/// how long a refresh token can be exchanged for a new access token
+pub const REFRESH_TOKEN_LIFETIME: Duration = Duration::days(30);
+
+/// scope for administrative endpoints, it has to be granted explicitly and isn't covered by `*`
+pub const ADMIN_SCOPE: &str = "admin";
 
 #[derive(Clone, FromRow)]
 pub struct User {
   pub id: i64,
   pub username: String,
+  pub country_code: String,
   pub joined_at: chrono::DateTime<chrono::Utc>,
+  pub is_supporter: bool,
+  pub is_bot: bool,
+  pub profile_colour: Option<String>,
+  pub title: Option<String>,
+  /// json encoded list
+  pub playstyle: String,
+  /// image hash of an uploaded avatar
+  pub avatar_hash: Option<String>,
+  pub cover_hash: Option<String>,
+  pub pm_friends_only: bool,
+}
+
+impl User {
+  /// Link to the user's avatar, the bundled default one when they haven't uploaded any
+  pub fn avatar_url(&self, config: &Config) -> String {
+    config.image_url(self.avatar_hash.as_deref().unwrap_or(images::DEFAULT_AVATAR))
+  }
+
+  pub fn cover_url(&self, config: &Config) -> String {
+    config.image_url(self.cover_hash.as_deref().unwrap_or(images::DEFAULT_COVER))
+  }
 }
 
 pub type UserExtension = Extension<User>;
 
-pub async fn middleware(
-  State(state): State<FiberState>,
-  mut request: Request,
-  next: Next
-) -> Response {
-  let Some(auth) = request.headers().get(header::AUTHORIZATION) else {
+#[derive(Clone, FromRow)]
+pub struct Token {
+  pub id: i64,
+  pub client_id: Option<i64>,
+  pub user_id: Option<i64>,
+  pub access_token: String,
+  pub refresh_token: Option<String>,
+  pub scopes: String,
+  pub created_at: DateTime<Utc>,
+  pub expires_at: DateTime<Utc>,
+  pub refresh_expires_at: DateTime<Utc>,
+  pub revoked: bool,
+  pub verified: bool,
+  pub verification_code: Option<String>,
+  pub verification_sent_at: Option<DateTime<Utc>>,
+  pub verification_attempts: i64,
+}
+
+#[derive(Clone, FromRow)]
+pub struct OAuthClient {
+  pub id: i64,
+  pub name: String,
+  pub secret_hash: String,
+  pub scopes: String,
+}
+
+impl OAuthClient {
+  /// Compares the secret in constant time, so response times don't leak how much of it matched
+  pub fn verify_secret(&self, secret: &str) -> bool {
+    hash_secret(secret).as_bytes().ct_eq(self.secret_hash.as_bytes()).into()
+  }
+
+  pub fn allows_scope(&self, scope: &str) -> bool {
+    self.scopes.split(' ')
+      .any(|s| s == scope || (s == "*" && scope != ADMIN_SCOPE))
+  }
+}
+
+impl Token {
+  pub fn is_valid(&self) -> bool {
+    !self.revoked && self.expires_at > Utc::now()
+  }
+
+  /// Whether the token was granted the scope, `identify` is implied for every token like on osu-web
+  pub fn has_scope(&self, scope: &str) -> bool {
+    scope == "identify" || self.scopes.split(' ')
+      .any(|s| s == scope || (s == "*" && scope != ADMIN_SCOPE))
+  }
+
+  pub fn expires_in(&self) -> i64 {
+    (self.expires_at - Utc::now()).num_seconds().max(0)
+  }
+}
+
+pub fn generate_secret(length: usize) -> String {
+  rand::rng()
+    .sample_iter(&Alphanumeric)
+    .take(length)
+    .map(char::from)
+    .collect()
+}
+
+/// Client secrets are long and random, so a plain sha256 keeps them out of the database without a slow password hash
+pub fn hash_secret(secret: &str) -> String {
+  hex::encode(Sha256::digest(secret))
+}
+
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+  let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>())
+    .map_err(|e| anyhow::anyhow!(e))?;
+
+  Argon2::default()
+    .hash_password(password.as_bytes(), &salt)
+    .map(|hash| hash.to_string())
+    .map_err(|e| anyhow::anyhow!(e))
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+  let Ok(hash) = PasswordHash::new(hash) else {
+    return false
+  };
+
+  Argon2::default()
+    .verify_password(password.as_bytes(), &hash)
+    .is_ok()
+}
+
+/// Issues a new access/refresh token pair.
+///
+/// Unverified tokens can only be used for session verification until the user enters their code.
+pub async fn issue_token<'e>(executor: impl Executor<'e, Database = Sqlite>, client_id: i64, user_id: Option<i64>, scopes: &str, verified: bool) -> sqlx::Result<Token> {
+  let now = Utc::now();
+
+  sqlx::query_as::<_, Token>(r#"
+    insert into oauth_tokens (client_id, user_id, access_token, refresh_token, scopes, created_at, expires_at, refresh_expires_at, verified)
+    values (?, ?, ?, ?, ?, ?, ?, ?, ?)
+    returning *
+  "#)
+    .bind(client_id)
+    .bind(user_id)
+    .bind(generate_secret(64))
+    .bind(generate_secret(64))
+    .bind(scopes)
+    .bind(now)
+    .bind(now + ACCESS_TOKEN_LIFETIME)
+    .bind(now + REFRESH_TOKEN_LIFETIME)
+    .bind(verified)
+    .fetch_one(executor)
+    .await
+}
+
+pub async fn revoke_token(pool: &SqlitePool, id: i64) -> sqlx::Result<()> {
+  sqlx::query(r#"
+    update oauth_tokens
+    set revoked = true
+    where id = ?
+  "#)
+    .bind(id)
+    .execute(pool)
+    .await?;
+
+  Ok(())
+}
+
+/// Looks up the bearer token of a request
+async fn authenticate(state: &FiberState, headers: &HeaderMap) -> Result<Token, Response> {
+  let Some(auth) = headers.get(header::AUTHORIZATION) else {
     println!("no auth header");
-    return StatusCode::UNAUTHORIZED.into_response()
+    return Err(StatusCode::UNAUTHORIZED.into_response())
   };
 
-  let Ok(token) = auth.to_str() else {
+  let Ok(auth) = auth.to_str() else {
     println!("token to_str failed");
-    return StatusCode::UNAUTHORIZED.into_response()
+    return Err(StatusCode::UNAUTHORIZED.into_response())
   };
 
-  println!("{}", token);
-
-  let Some((_, id)) = token.split_once(' ') else {
+  let Some(access_token) = auth.split_once(' ')
+    .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+    .map(|(_, token)| token) else
+  {
     println!("token split failed");
-    return StatusCode::UNAUTHORIZED.into_response()
+    return Err(StatusCode::UNAUTHORIZED.into_response())
   };
 
-  let Ok(id) = id.parse::<i64>() else {
-    println!("token parse failed");
-    return StatusCode::UNAUTHORIZED.into_response()
+  let Ok(token) = sqlx::query_as::<_, Token>(r#"
+    select * from oauth_tokens
+    where access_token = ?
+  "#)
+    .bind(access_token)
+    .fetch_one(&state.pool)
+    .await else
+  {
+    println!("unknown token");
+    return Err(StatusCode::UNAUTHORIZED.into_response())
   };
 
-  let Ok(user) = sqlx::query_as::<_, User>(r#"
+  if !token.is_valid() {
+    println!("token expired or revoked");
+    return Err(StatusCode::UNAUTHORIZED.into_response())
+  }
+
+  Ok(token)
+}
+
+async fn find_user(state: &FiberState, id: i64) -> Option<User> {
+  sqlx::query_as::<_, User>(r#"
     select * from users
     where id = ?
   "#)
     .bind(id)
     .fetch_one(&state.pool)
-    .await else
-  {
-    return StatusCode::UNAUTHORIZED.into_response()
+    .await
+    .ok()
+}
+
+/// Response for requests made before the session was verified
+fn verification_required() -> Response {
+  (StatusCode::UNAUTHORIZED, Json(json!({ "authentication": "verify" }))).into_response()
+}
+
+async fn authenticate_user(state: &FiberState, headers: &HeaderMap) -> Result<(Token, User), Response> {
+  let token = authenticate(state, headers).await?;
+
+  let Some(user_id) = token.user_id else {
+    return Err(StatusCode::UNAUTHORIZED.into_response())
+  };
+
+  let Some(user) = find_user(state, user_id).await else {
+    return Err(StatusCode::UNAUTHORIZED.into_response())
+  };
+
+  Ok((token, user))
+}
+
+/// Requires a verified token issued to a user with `*` or the route's scope, used by everything acting on behalf of someone
+pub async fn middleware(
+  State((state, scope)): State<(FiberState, &'static str)>,
+  mut request: Request,
+  next: Next
+) -> Response {
+  let (token, user) = match authenticate_user(&state, request.headers()).await {
+    Ok(auth) => auth,
+    Err(response) => return response,
+  };
+
+  if !token.has_scope(scope) {
+    return StatusCode::FORBIDDEN.into_response()
+  }
+
+  if !token.verified {
+    return verification_required()
+  }
+
+  request.extensions_mut().insert(token);
+  request.extensions_mut().insert(user);
+
+  next.run(request).await
+}
+
+/// Same as [`middleware`], but lets unverified sessions through so they can be verified
+pub async fn unverified_middleware(
+  State((state, scope)): State<(FiberState, &'static str)>,
+  mut request: Request,
+  next: Next
+) -> Response {
+  let (token, user) = match authenticate_user(&state, request.headers()).await {
+    Ok(auth) => auth,
+    Err(response) => return response,
   };
 
+  if !token.has_scope(scope) {
+    return StatusCode::FORBIDDEN.into_response()
+  }
+
+  request.extensions_mut().insert(token);
   request.extensions_mut().insert(user);
 
+  next.run(request).await
+}
+
+/// Accepts any token with the `public` scope, including client credentials tokens without a user
+pub async fn public_middleware(
+  State(state): State<FiberState>,
+  mut request: Request,
+  next: Next
+) -> Response {
+  let token = match authenticate(&state, request.headers()).await {
+    Ok(token) => token,
+    Err(response) => return response,
+  };
+
+  if !token.has_scope("public") {
+    return StatusCode::FORBIDDEN.into_response()
+  }
+
+  if !token.verified {
+    return verification_required()
+  }
+
+  if let Some(user_id) = token.user_id {
+    let Some(user) = find_user(&state, user_id).await else {
+      return StatusCode::UNAUTHORIZED.into_response()
+    };
+
+    request.extensions_mut().insert(user);
+  }
+
+  request.extensions_mut().insert(token);
+
+  next.run(request).await
+}
+
+/// Requires a verified token with the [`ADMIN_SCOPE`], with or without a user
+pub async fn admin_middleware(
+  State(state): State<FiberState>,
+  mut request: Request,
+  next: Next
+) -> Response {
+  let token = match authenticate(&state, request.headers()).await {
+    Ok(token) => token,
+    Err(response) => return response,
+  };
+
+  if !token.has_scope(ADMIN_SCOPE) {
+    return StatusCode::FORBIDDEN.into_response()
+  }
+
+  if !token.verified {
+    return verification_required()
+  }
+
+  request.extensions_mut().insert(token);
+
   next.run(request).await
 }
\ No newline at end of file
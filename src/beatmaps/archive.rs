@@ -0,0 +1,88 @@
+use std::io::{Cursor, Write};
+
+use anyhow::Result;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::state::FiberState;
+
+const VIDEO_EXTENSIONS: [&str; 10] = ["avi", "flv", "m4v", "mkv", "mov", "mp4", "mpeg", "mpg", "webm", "wmv"];
+
+/// formats that are compressed already, deflating them again only costs time
+const COMPRESSED_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "mp3", "ogg", "webm"];
+
+fn extension(filename: &str) -> String {
+  filename.rsplit_once('.')
+    .map(|(_, ext)| ext.to_lowercase())
+    .unwrap_or_default()
+}
+
+pub fn is_video(filename: &str) -> bool {
+  VIDEO_EXTENSIONS.contains(&extension(filename).as_str())
+}
+
+fn build_archive(files: Vec<(String, Vec<u8>)>) -> Result<Vec<u8>> {
+  let mut zip = ZipWriter::new(Cursor::new(vec![]));
+
+  for (filename, data) in files {
+    let method = match COMPRESSED_EXTENSIONS.contains(&extension(&filename).as_str()) {
+      true => CompressionMethod::Stored,
+      false => CompressionMethod::Deflated,
+    };
+
+    let options = SimpleFileOptions::default()
+      .compression_method(method)
+      .large_file(data.len() as u64 >= u32::MAX as u64);
+
+    zip.start_file(filename, options)?;
+    zip.write_all(&data)?;
+  }
+
+  Ok(zip.finish()?.into_inner())
+}
+
+/// Returns the hash of the set's archive with every video left out, building it the first time it's asked for
+pub async fn no_video_archive(state: &FiberState, beatmapset_id: i64) -> Result<String> {
+  let cached = sqlx::query_scalar::<_, Option<String>>(r#"
+    select no_video_archive_hash from beatmapsets
+    where id = ?
+  "#)
+    .bind(beatmapset_id)
+    .fetch_one(&state.pool)
+    .await?;
+
+  if let Some(hash) = cached {
+    return Ok(hash);
+  }
+
+  let entries = sqlx::query_as::<_, (String, String)>(r#"
+    select filename, hash from beatmapset_files
+    where beatmapset_id = ?
+    order by filename
+  "#)
+    .bind(beatmapset_id)
+    .fetch_all(&state.pool)
+    .await?;
+
+  let mut files = vec![];
+
+  for (filename, hash) in entries {
+    if !is_video(&filename) {
+      files.push((filename, state.storage.get(&hash).await?));
+    }
+  }
+
+  let data = tokio::task::spawn_blocking(move || build_archive(files)).await??;
+  let hash = state.storage.put(&data).await?;
+
+  sqlx::query(r#"
+    update beatmapsets
+    set no_video_archive_hash = ?
+    where id = ?
+  "#)
+    .bind(&hash)
+    .bind(beatmapset_id)
+    .execute(&state.pool)
+    .await?;
+
+  Ok(hash)
+}
\ No newline at end of file
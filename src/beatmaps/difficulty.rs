@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use rosu_mods::{serde::GameModsSeed, GameMode as ModsMode, GameMods as LazerMods};
+use rosu_pp::{any::DifficultyAttributes, model::mode::GameMode, Beatmap, Difficulty, GameMods, Performance};
+use serde::de::DeserializeSeed;
+use serde_json::{json, Value};
+
+use crate::{ruleset::Ruleset, state::FiberState};
+
+pub fn mods_mode(ruleset: Ruleset) -> ModsMode {
+  match ruleset {
+    Ruleset::Osu => ModsMode::Osu,
+    Ruleset::Taiko => ModsMode::Taiko,
+    Ruleset::Fruits => ModsMode::Catch,
+    Ruleset::Mania => ModsMode::Mania,
+  }
+}
+
+/// Parses mods in the client's `APIMod` format, keeping their settings
+pub fn parse_mods(mods: &[Value], ruleset: Ruleset) -> GameMods {
+  GameModsSeed::Mode { mode: mods_mode(ruleset), deny_unknown_fields: false }
+    .deserialize(Value::Array(mods.to_vec()))
+    .unwrap_or_else(|_| LazerMods::new())
+    .into()
+}
+
+fn game_mode(ruleset: Ruleset) -> GameMode {
+  match ruleset {
+    Ruleset::Osu => GameMode::Osu,
+    Ruleset::Taiko => GameMode::Taiko,
+    Ruleset::Fruits => GameMode::Catch,
+    Ruleset::Mania => GameMode::Mania,
+  }
+}
+
+/// Parses a `.osu` file, converting it to `ruleset` if it's played in a different one
+pub fn parse_beatmap(data: &[u8], ruleset: Ruleset, mods: &GameMods) -> Result<Beatmap> {
+  let mut map = Beatmap::from_bytes(data)?;
+
+  map.convert_mut(game_mode(ruleset), mods)
+    .map_err(|e| anyhow!("{}", e))?;
+
+  Ok(map)
+}
+
+/// Calculates the difficulty of a beatmap, refusing maps that would take unreasonably long to calculate
+pub fn difficulty(map: &Beatmap, mods: &GameMods) -> Result<DifficultyAttributes> {
+  Difficulty::new()
+    .mods(mods.clone())
+    .checked_calculate(map)
+    .map_err(|e| anyhow!("{}", e))
+}
+
+/// Whether a play is scored the way lazer scores it, which is the case unless classic is enabled
+pub fn is_lazer(mods: &[Value]) -> bool {
+  !mods.iter().any(|m| m.get("acronym").and_then(Value::as_str) == Some("CL"))
+}
+
+/// Calculates the performance of a play from its hit statistics
+pub fn performance(attributes: DifficultyAttributes, mods: &GameMods, lazer: bool, max_combo: u32, statistics: &HashMap<String, u32>) -> f64 {
+  let count = |result: &str| statistics.get(result).copied().unwrap_or_default();
+
+  let performance = Performance::new(attributes)
+    .mods(mods.clone())
+    .combo(max_combo)
+    .misses(count("miss"))
+    .lazer(lazer);
+
+  let performance = match performance {
+    Performance::Osu(_) | Performance::Taiko(_) => performance
+      .n300(count("great"))
+      .n100(count("ok"))
+      .n50(count("meh"))
+      .large_tick_hits(count("large_tick_hit"))
+      .small_tick_hits(count("small_tick_hit"))
+      .slider_end_hits(count("slider_tail_hit")),
+    // fruits are greats, droplets large ticks and tiny droplets small ticks
+    Performance::Catch(_) => performance
+      .n300(count("great"))
+      .n100(count("large_tick_hit"))
+      .n50(count("small_tick_hit"))
+      .n_katu(count("small_tick_miss")),
+    Performance::Mania(_) => performance
+      .n_geki(count("perfect"))
+      .n300(count("great"))
+      .n_katu(count("good"))
+      .n100(count("ok"))
+      .n50(count("meh")),
+  };
+
+  let pp = performance.calculate().pp();
+
+  if pp.is_finite() { pp } else { 0. }
+}
+
+/// Attributes in the format of osu-web's `/beatmaps/{id}/attributes`
+pub fn attributes_json(attributes: &DifficultyAttributes) -> Value {
+  let mut value = json!({
+    "star_rating": attributes.stars(),
+    "max_combo": attributes.max_combo(),
+  });
+
+  let extra = match attributes {
+    DifficultyAttributes::Osu(attributes) => json!({
+      "aim_difficulty": attributes.aim,
+      "aim_difficult_slider_count": attributes.aim_difficult_slider_count,
+      "speed_difficulty": attributes.speed,
+      "speed_note_count": attributes.speed_note_count,
+      "slider_factor": attributes.slider_factor,
+      "aim_difficult_strain_count": attributes.aim_difficult_strain_count,
+      "speed_difficult_strain_count": attributes.speed_difficult_strain_count,
+      "flashlight_difficulty": attributes.flashlight,
+      "approach_rate": attributes.ar,
+      "overall_difficulty": attributes.od(),
+    }),
+    DifficultyAttributes::Taiko(attributes) => json!({
+      "stamina_difficulty": attributes.stamina,
+      "rhythm_difficulty": attributes.rhythm,
+      "colour_difficulty": attributes.color,
+      "reading_difficulty": attributes.reading,
+      "mono_stamina_factor": attributes.mono_stamina_factor,
+      "great_hit_window": attributes.great_hit_window,
+    }),
+    DifficultyAttributes::Catch(_) | DifficultyAttributes::Mania(_) => json!({}),
+  };
+
+  if let (Some(value), Value::Object(extra)) = (value.as_object_mut(), extra) {
+    value.extend(extra);
+  }
+
+  value
+}
+
+/// Loads a beatmap's `.osu` file from storage
+pub async fn load_osu_file(state: &FiberState, beatmap_id: i64) -> Result<Option<Vec<u8>>> {
+  let hash = sqlx::query_scalar::<_, String>(r#"
+    select f.hash from beatmaps b
+    join beatmapset_files f on f.beatmapset_id = b.beatmapset_id and f.filename = b.filename
+    where b.id = ?
+  "#)
+    .bind(beatmap_id)
+    .fetch_optional(&state.pool)
+    .await?;
+
+  let Some(hash) = hash else {
+    return Ok(None);
+  };
+
+  Ok(Some(state.storage.get(&hash).await?))
+}
+
+/// Calculates the difficulty of a stored beatmap played in `ruleset` with `mods`
+pub async fn beatmap_attributes(state: &FiberState, beatmap_id: i64, ruleset: Ruleset, mods: Vec<Value>) -> Result<Option<DifficultyAttributes>> {
+  let Some(data) = load_osu_file(state, beatmap_id).await? else {
+    return Ok(None);
+  };
+
+  let attributes = tokio::task::spawn_blocking(move || {
+    let mods = parse_mods(&mods, ruleset);
+    let map = parse_beatmap(&data, ruleset, &mods)?;
+
+    difficulty(&map, &mods)
+  }).await??;
+
+  Ok(Some(attributes))
+}
+
+/// Calculates the pp of a play on a stored beatmap
+pub async fn score_performance(
+  state: &FiberState,
+  beatmap_id: i64,
+  ruleset: Ruleset,
+  mods: Vec<Value>,
+  max_combo: u32,
+  statistics: HashMap<String, u32>,
+) -> Result<Option<f64>> {
+  let Some(data) = load_osu_file(state, beatmap_id).await? else {
+    return Ok(None);
+  };
+
+  let pp = tokio::task::spawn_blocking(move || {
+    let lazer = is_lazer(&mods);
+    let mods = parse_mods(&mods, ruleset);
+    let map = parse_beatmap(&data, ruleset, &mods)?;
+    let attributes = difficulty(&map, &mods)?;
+
+    anyhow::Ok(performance(attributes, &mods, lazer, max_combo, &statistics))
+  }).await??;
+
+  Ok(Some(pp))
+}
\ No newline at end of file
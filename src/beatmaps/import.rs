@@ -0,0 +1,302 @@
+use std::io::{Cursor, Read};
+
+use anyhow::{bail, Result};
+use chrono::Utc;
+use md5::{Digest, Md5};
+use zip::ZipArchive;
+
+use crate::{beatmaps::{difficulty, parser::OsuFile}, ruleset::Ruleset, state::FiberState};
+
+/// upper bound for everything inside one archive, so a zip bomb can't fill the disk
+const MAX_UNPACKED_SIZE: u64 = 512 * 1024 * 1024;
+
+struct ArchiveFile {
+  filename: String,
+  data: Vec<u8>,
+}
+
+struct ArchiveBeatmap {
+  filename: String,
+  checksum: String,
+  osu: OsuFile,
+  difficulty_rating: f64,
+  max_combo: u32,
+}
+
+struct Archive {
+  files: Vec<ArchiveFile>,
+  beatmaps: Vec<ArchiveBeatmap>,
+  has_storyboard: bool,
+}
+
+/// Unpacks an archive and parses every difficulty in it
+fn read_archive(data: &[u8]) -> Result<Archive> {
+  let mut zip = ZipArchive::new(Cursor::new(data))?;
+
+  let mut files = vec![];
+  let mut beatmaps = vec![];
+  let mut has_storyboard = false;
+  let mut unpacked = 0;
+
+  for i in 0..zip.len() {
+    let mut file = zip.by_index(i)?;
+
+    if file.is_dir() {
+      continue;
+    }
+
+    if file.enclosed_name().is_none() {
+      bail!("archive contains an unsafe path: {}", file.name()?);
+    }
+
+    unpacked += file.size();
+
+    if unpacked > MAX_UNPACKED_SIZE {
+      bail!("archive is too large once unpacked");
+    }
+
+    let filename = file.name()?.replace('\\', "/");
+
+    let mut data = Vec::with_capacity(file.size() as usize);
+    file.read_to_end(&mut data)?;
+
+    let lowercase = filename.to_lowercase();
+
+    if lowercase.ends_with(".osb") {
+      has_storyboard = true;
+    }
+
+    if lowercase.ends_with(".osu") {
+      let osu = OsuFile::parse(&String::from_utf8_lossy(&data))
+        .map_err(|e| anyhow::anyhow!("{}: {}", filename, e))?;
+
+      // a map the calculator can't handle still gets imported, falling back to the object count for its combo
+      let ruleset = Ruleset::from_id(osu.ruleset_id).unwrap_or(Ruleset::Osu);
+      let mods = Default::default();
+      let attributes = difficulty::parse_beatmap(&data, ruleset, &mods)
+        .and_then(|map| difficulty::difficulty(&map, &mods));
+
+      if let Err(e) = &attributes {
+        eprintln!("failed to calculate the difficulty of {}: {}", filename, e);
+      }
+
+      beatmaps.push(ArchiveBeatmap {
+        filename: filename.clone(),
+        checksum: hex::encode(Md5::digest(&data)),
+        difficulty_rating: attributes.as_ref().map_or(0., |attributes| attributes.stars()),
+        max_combo: attributes.as_ref().map_or(osu.count_circles + osu.count_sliders + osu.count_spinners, |attributes| attributes.max_combo()),
+        osu,
+      });
+    }
+
+    files.push(ArchiveFile { filename, data });
+  }
+
+  if beatmaps.is_empty() {
+    bail!("archive doesn't contain any beatmaps");
+  }
+
+  Ok(Archive { files, beatmaps, has_storyboard })
+}
+
+/// Imports a `.osz` archive, returning the id of the created or updated beatmapset.
+///
+/// Ids from the `.osu` files are kept when they're set, so archives from the official servers keep their ids.
+pub async fn import_osz(state: &FiberState, data: Vec<u8>, status: i64) -> Result<i64> {
+  let (data, archive) = tokio::task::spawn_blocking(move || {
+    let archive = read_archive(&data);
+    (data, archive)
+  }).await?;
+  let archive = archive?;
+
+  let archive_hash = state.storage.put(&data).await?;
+
+  let mut hashes = Vec::with_capacity(archive.files.len());
+  for file in &archive.files {
+    hashes.push(state.storage.put(&file.data).await?);
+  }
+
+  let metadata = &archive.beatmaps[0].osu;
+  let now = Utc::now();
+  let ranked_at = (status > 0).then_some(now);
+
+  let mut tx = state.pool.begin().await?;
+
+  // fall back to the set an already imported difficulty belongs to, so reimporting updates in place
+  let mut beatmapset_id = metadata.beatmapset_id;
+
+  if beatmapset_id.is_none() {
+    for beatmap in &archive.beatmaps {
+      beatmapset_id = sqlx::query_scalar::<_, i64>(r#"
+        select beatmapset_id from beatmaps
+        where checksum = ?
+      "#)
+        .bind(&beatmap.checksum)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+      if beatmapset_id.is_some() {
+        break;
+      }
+    }
+  }
+
+  let creator_id = sqlx::query_scalar::<_, i64>(r#"
+    select id from users
+    where username = ?
+  "#)
+    .bind(&metadata.creator)
+    .fetch_optional(&mut *tx)
+    .await?
+    .unwrap_or(0);
+
+  let beatmapset_id = sqlx::query_scalar::<_, i64>(r#"
+    insert into beatmapsets (
+      id, artist, artist_unicode, title, title_unicode, creator, creator_id, source, tags,
+      status, has_video, has_storyboard, submitted_at, ranked_at, updated_at, archive_hash
+    )
+    values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+    on conflict (id) do update set
+      artist = excluded.artist,
+      artist_unicode = excluded.artist_unicode,
+      title = excluded.title,
+      title_unicode = excluded.title_unicode,
+      creator = excluded.creator,
+      creator_id = excluded.creator_id,
+      source = excluded.source,
+      tags = excluded.tags,
+      status = excluded.status,
+      has_video = excluded.has_video,
+      has_storyboard = excluded.has_storyboard,
+      ranked_at = coalesce(beatmapsets.ranked_at, excluded.ranked_at),
+      updated_at = excluded.updated_at,
+      archive_hash = excluded.archive_hash,
+      no_video_archive_hash = null
+    returning id
+  "#)
+    .bind(beatmapset_id)
+    .bind(&metadata.artist)
+    .bind(&metadata.artist_unicode)
+    .bind(&metadata.title)
+    .bind(&metadata.title_unicode)
+    .bind(&metadata.creator)
+    .bind(creator_id)
+    .bind(&metadata.source)
+    .bind(&metadata.tags)
+    .bind(status)
+    .bind(archive.beatmaps.iter().any(|beatmap| beatmap.osu.has_video))
+    .bind(archive.has_storyboard)
+    .bind(now)
+    .bind(ranked_at)
+    .bind(now)
+    .bind(&archive_hash)
+    .fetch_one(&mut *tx)
+    .await?;
+
+  let mut beatmap_ids = Vec::with_capacity(archive.beatmaps.len());
+
+  for beatmap in &archive.beatmaps {
+    let osu = &beatmap.osu;
+
+    // an identical file that's already imported keeps its id, otherwise the id from the file is used
+    let beatmap_id = sqlx::query_scalar::<_, i64>(r#"
+      select id from beatmaps
+      where checksum = ?
+    "#)
+      .bind(&beatmap.checksum)
+      .fetch_optional(&mut *tx)
+      .await?
+      .or(osu.beatmap_id);
+
+    let beatmap_id = sqlx::query_scalar::<_, i64>(r#"
+      insert into beatmaps (
+        id, beatmapset_id, version, checksum, filename, ruleset_id, status, difficulty_rating,
+        drain, cs, od, ar, bpm, total_length, hit_length, max_combo,
+        count_circles, count_sliders, count_spinners, updated_at
+      )
+      values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+      on conflict (id) do update set
+        beatmapset_id = excluded.beatmapset_id,
+        version = excluded.version,
+        checksum = excluded.checksum,
+        filename = excluded.filename,
+        ruleset_id = excluded.ruleset_id,
+        status = excluded.status,
+        difficulty_rating = excluded.difficulty_rating,
+        drain = excluded.drain,
+        cs = excluded.cs,
+        od = excluded.od,
+        ar = excluded.ar,
+        bpm = excluded.bpm,
+        total_length = excluded.total_length,
+        hit_length = excluded.hit_length,
+        max_combo = excluded.max_combo,
+        count_circles = excluded.count_circles,
+        count_sliders = excluded.count_sliders,
+        count_spinners = excluded.count_spinners,
+        updated_at = excluded.updated_at
+      returning id
+    "#)
+      .bind(beatmap_id)
+      .bind(beatmapset_id)
+      .bind(&osu.version)
+      .bind(&beatmap.checksum)
+      .bind(&beatmap.filename)
+      .bind(osu.ruleset_id)
+      .bind(status)
+      .bind(beatmap.difficulty_rating)
+      .bind(osu.hp)
+      .bind(osu.cs)
+      .bind(osu.od)
+      .bind(osu.ar)
+      .bind(osu.bpm)
+      .bind(osu.total_length)
+      .bind(osu.hit_length)
+      .bind(beatmap.max_combo)
+      .bind(osu.count_circles)
+      .bind(osu.count_sliders)
+      .bind(osu.count_spinners)
+      .bind(now)
+      .fetch_one(&mut *tx)
+      .await?;
+
+    beatmap_ids.push(beatmap_id);
+  }
+
+  // difficulties that were taken out of the set go away along with their scores
+  sqlx::query(r#"
+    delete from beatmaps
+    where beatmapset_id = ? and id not in (select value from json_each(?))
+  "#)
+    .bind(beatmapset_id)
+    .bind(serde_json::json!(beatmap_ids).to_string())
+    .execute(&mut *tx)
+    .await?;
+
+  sqlx::query(r#"
+    delete from beatmapset_files
+    where beatmapset_id = ?
+  "#)
+    .bind(beatmapset_id)
+    .execute(&mut *tx)
+    .await?;
+
+  for (file, hash) in archive.files.iter().zip(hashes) {
+    sqlx::query(r#"
+      insert into beatmapset_files (beatmapset_id, filename, hash, size) values
+      (?, ?, ?, ?)
+    "#)
+      .bind(beatmapset_id)
+      .bind(&file.filename)
+      .bind(hash)
+      .bind(file.data.len() as i64)
+      .execute(&mut *tx)
+      .await?;
+  }
+
+  tx.commit().await?;
+
+  println!("imported beatmapset {} ({} - {}, {} beatmaps)", beatmapset_id, metadata.artist, metadata.title, archive.beatmaps.len());
+
+  Ok(beatmapset_id)
+}
\ No newline at end of file
@@ -0,0 +1,5 @@
+pub mod archive;
+pub mod difficulty;
+pub mod import;
+pub mod parser;
+pub mod status;
\ No newline at end of file
@@ -0,0 +1,230 @@
+use std::{collections::HashMap, error::Error, fmt::Display};
+
+#[derive(Debug)]
+pub enum OsuFileError {
+  InvalidHeader,
+  MissingField(&'static str),
+}
+
+impl Display for OsuFileError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:?}", self)
+  }
+}
+
+impl Error for OsuFileError {}
+
+/// The parts of a `.osu` file we keep in the database
+#[derive(Debug, Clone)]
+pub struct OsuFile {
+  pub ruleset_id: u32,
+  pub audio_filename: String,
+  pub preview_time: i64,
+
+  pub title: String,
+  pub title_unicode: Option<String>,
+  pub artist: String,
+  pub artist_unicode: Option<String>,
+  pub creator: String,
+  pub version: String,
+  pub source: String,
+  pub tags: String,
+  pub beatmap_id: Option<i64>,
+  pub beatmapset_id: Option<i64>,
+
+  pub hp: f32,
+  pub cs: f32,
+  pub od: f32,
+  pub ar: f32,
+
+  pub bpm: f64,
+  pub has_video: bool,
+  pub count_circles: u32,
+  pub count_sliders: u32,
+  pub count_spinners: u32,
+  /// seconds from the start of the track to the last object
+  pub total_length: u32,
+  /// seconds between the first and last object, without breaks
+  pub hit_length: u32,
+}
+
+struct TimingPoint {
+  time: f64,
+  beat_length: f64,
+}
+
+fn parse_key_value(line: &str) -> Option<(&str, &str)> {
+  line.split_once(':')
+    .map(|(key, value)| (key.trim(), value.trim()))
+}
+
+/// Picks the bpm that's active for the longest time, the same way the game does
+fn main_bpm(timing_points: &[TimingPoint], last_time: f64) -> f64 {
+  let mut durations = HashMap::<u64, f64>::new();
+
+  for (i, point) in timing_points.iter().enumerate() {
+    let end = timing_points.get(i + 1)
+      .map(|next| next.time)
+      .unwrap_or(last_time)
+      .max(point.time);
+
+    // rounding keeps float noise from splitting the same bpm
+    let beat_length = (point.beat_length * 1000.).round() as u64;
+
+    *durations.entry(beat_length).or_default() += end - point.time;
+  }
+
+  durations.into_iter()
+    .max_by(|a, b| a.1.total_cmp(&b.1))
+    .map(|(beat_length, _)| 60_000. / (beat_length as f64 / 1000.))
+    .unwrap_or_default()
+}
+
+impl OsuFile {
+  pub fn parse(data: &str) -> Result<Self, OsuFileError> {
+    let mut lines = data.trim_start_matches('\u{feff}').lines();
+
+    if !lines.next().is_some_and(|header| header.trim().starts_with("osu file format")) {
+      return Err(OsuFileError::InvalidHeader);
+    }
+
+    let mut section = "";
+    let mut values = HashMap::<&str, &str>::new();
+    let mut timing_points = vec![];
+    let mut breaks = vec![];
+    let mut has_video = false;
+
+    let (mut count_circles, mut count_sliders, mut count_spinners) = (0, 0, 0);
+    let (mut first_time, mut last_time) = (None::<f64>, 0f64);
+
+    for line in lines {
+      let line = line.trim();
+
+      if line.is_empty() || line.starts_with("//") {
+        continue;
+      }
+
+      if line.starts_with('[') && line.ends_with(']') {
+        section = &line[1..line.len() - 1];
+        continue;
+      }
+
+      match section {
+        "General" | "Metadata" | "Difficulty" => {
+          if let Some((key, value)) = parse_key_value(line) {
+            values.insert(key, value);
+          }
+        },
+        "Events" => {
+          let fields = line.split(',').collect::<Vec<_>>();
+
+          match fields.as_slice() {
+            ["Video" | "1", ..] => has_video = true,
+            ["2" | "Break", start, end, ..] => {
+              if let (Ok(start), Ok(end)) = (start.parse::<f64>(), end.parse::<f64>()) {
+                breaks.push(end - start);
+              }
+            },
+            _ => {},
+          }
+        },
+        "TimingPoints" => {
+          let fields = line.split(',').collect::<Vec<_>>();
+
+          let (Some(Ok(time)), Some(Ok(beat_length))) = (
+            fields.first().map(|f| f.parse::<f64>()),
+            fields.get(1).map(|f| f.parse::<f64>()),
+          ) else {
+            continue
+          };
+
+          // inherited points only change slider velocity
+          let uninherited = fields.get(6).is_none_or(|f| *f == "1");
+
+          if uninherited && beat_length > 0. {
+            timing_points.push(TimingPoint { time, beat_length });
+          }
+        },
+        "HitObjects" => {
+          let fields = line.split(',').collect::<Vec<_>>();
+
+          let (Some(Ok(time)), Some(Ok(ty))) = (
+            fields.get(2).map(|f| f.parse::<f64>()),
+            fields.get(3).map(|f| f.parse::<u32>()),
+          ) else {
+            continue
+          };
+
+          let mut end_time = time;
+
+          if ty & 1 != 0 {
+            count_circles += 1;
+          } else if ty & 2 != 0 {
+            count_sliders += 1;
+          } else if ty & 8 != 0 {
+            count_spinners += 1;
+            end_time = fields.get(5).and_then(|f| f.parse().ok()).unwrap_or(time);
+          } else if ty & 128 != 0 {
+            // mania holds count as sliders, their end time comes before the hit sample
+            count_sliders += 1;
+            end_time = fields.get(5)
+              .and_then(|f| f.split(':').next())
+              .and_then(|f| f.parse().ok())
+              .unwrap_or(time);
+          }
+
+          first_time = Some(first_time.map_or(time, |first| first.min(time)));
+          last_time = last_time.max(end_time);
+        },
+        _ => {},
+      }
+    }
+
+    let get = |key: &'static str| values.get(key)
+      .copied()
+      .ok_or(OsuFileError::MissingField(key));
+    let get_or = |key: &str, default: &'static str| values.get(key)
+      .copied()
+      .unwrap_or(default);
+    let get_id = |key: &str| values.get(key)
+      .and_then(|value| value.parse::<i64>().ok())
+      .filter(|id| *id > 0);
+    let get_unicode = |key: &str| values.get(key)
+      .filter(|value| !value.is_empty())
+      .map(|value| value.to_string());
+
+    let od = get_or("OverallDifficulty", "5").parse().unwrap_or(5.);
+    let break_time = breaks.iter().sum::<f64>();
+
+    Ok(Self {
+      ruleset_id: get_or("Mode", "0").parse().unwrap_or(0),
+      audio_filename: get_or("AudioFilename", "").into(),
+      preview_time: get_or("PreviewTime", "-1").parse().unwrap_or(-1),
+
+      title: get("Title")?.into(),
+      title_unicode: get_unicode("TitleUnicode"),
+      artist: get("Artist")?.into(),
+      artist_unicode: get_unicode("ArtistUnicode"),
+      creator: get_or("Creator", "").into(),
+      version: get_or("Version", "").into(),
+      source: get_or("Source", "").into(),
+      tags: get_or("Tags", "").into(),
+      beatmap_id: get_id("BeatmapID"),
+      beatmapset_id: get_id("BeatmapSetID"),
+
+      hp: get_or("HPDrainRate", "5").parse().unwrap_or(5.),
+      cs: get_or("CircleSize", "5").parse().unwrap_or(5.),
+      od,
+      // really old maps don't have a separate approach rate
+      ar: values.get("ApproachRate").and_then(|ar| ar.parse().ok()).unwrap_or(od),
+
+      bpm: main_bpm(&timing_points, last_time),
+      has_video,
+      count_circles,
+      count_sliders,
+      count_spinners,
+      total_length: (last_time / 1000.).round() as u32,
+      hit_length: ((last_time - first_time.unwrap_or(last_time) - break_time).max(0.) / 1000.).round() as u32,
+    })
+  }
+}
\ No newline at end of file
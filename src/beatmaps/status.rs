@@ -0,0 +1,73 @@
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BeatmapStatus {
+  Graveyard,
+  Wip,
+  Pending,
+  Ranked,
+  Approved,
+  Qualified,
+  Loved,
+}
+
+impl BeatmapStatus {
+  pub const ALL: [BeatmapStatus; 7] = [
+    Self::Graveyard,
+    Self::Wip,
+    Self::Pending,
+    Self::Ranked,
+    Self::Approved,
+    Self::Qualified,
+    Self::Loved,
+  ];
+
+  /// Ids of the statuses `filter` accepts as a json list, for binding to `json_each` in queries
+  pub fn ids_where(filter: impl Fn(Self) -> bool) -> String {
+    let ids = Self::ALL.into_iter()
+      .filter(|status| filter(*status))
+      .map(Self::id)
+      .collect::<Vec<_>>();
+
+    serde_json::json!(ids).to_string()
+  }
+
+  /// Unknown ids are treated as pending
+  pub fn from_id(id: i64) -> Self {
+    Self::ALL.into_iter()
+      .find(|status| status.id() == id)
+      .unwrap_or(Self::Pending)
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    Self::ALL.into_iter()
+      .find(|status| status.name() == name)
+  }
+
+  pub fn id(self) -> i64 {
+    self as i64 - 2
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::Graveyard => "graveyard",
+      Self::Wip => "wip",
+      Self::Pending => "pending",
+      Self::Ranked => "ranked",
+      Self::Approved => "approved",
+      Self::Qualified => "qualified",
+      Self::Loved => "loved",
+    }
+  }
+
+  /// Whether scores set on the map show up on leaderboards
+  pub fn has_leaderboard(self) -> bool {
+    matches!(self, Self::Ranked | Self::Approved | Self::Qualified | Self::Loved)
+  }
+
+  /// Whether scores set on the map award pp
+  pub fn awards_pp(self) -> bool {
+    matches!(self, Self::Ranked | Self::Approved)
+  }
+}
\ No newline at end of file
@@ -0,0 +1,626 @@
+use std::{collections::{HashMap, VecDeque}, sync::Mutex, time::{Duration, Instant}};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Serialize;
+use sqlx::{prelude::FromRow, SqlitePool};
+
+use crate::{auth::User, config::Config, relations, routes::users::ApiUser};
+
+/// longest message that can be sent, in characters
+pub const MESSAGE_LENGTH_LIMIT: usize = 450;
+
+/// most messages returned at once
+pub const MESSAGE_LIMIT: i64 = 50;
+
+/// most messages a user can send within `RATE_LIMIT_WINDOW`
+const RATE_LIMIT_MESSAGES: usize = 10;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(5);
+
+/// most silences sent to the client at once
+const SILENCE_LIMIT: i64 = 100;
+
+/// why silenced users can't send messages
+pub const SILENCED: &str = "You can't talk while you're silenced.";
+
+pub const PUBLIC: &str = "PUBLIC";
+pub const PM: &str = "PM";
+
+#[derive(Clone, FromRow)]
+pub struct DbChannel {
+  pub id: i64,
+  pub name: String,
+  pub description: String,
+  #[sqlx(rename = "type")]
+  pub channel_type: String,
+  pub moderated: bool,
+  pub created_at: DateTime<Utc>,
+}
+
+/// A channel as seen by one of its (possible) members
+#[derive(Clone, FromRow)]
+pub struct DbChannelState {
+  #[sqlx(flatten)]
+  pub channel: DbChannel,
+  pub last_message_id: Option<i64>,
+  pub last_read_id: Option<i64>,
+}
+
+#[derive(Clone, FromRow)]
+pub struct DbMessage {
+  pub id: i64,
+  pub channel_id: i64,
+  pub sender_id: i64,
+  pub content: String,
+  pub is_action: bool,
+  pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct CurrentUserAttributes {
+  can_message: bool,
+  can_message_error: Option<&'static str>,
+  last_read_id: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct ApiChannel {
+  pub channel_id: i64,
+  pub name: String,
+  pub description: String,
+  pub icon: Option<String>,
+  #[serde(rename = "type")]
+  pub channel_type: String,
+  pub moderated: bool,
+  pub last_message_id: Option<i64>,
+  pub last_read_id: Option<i64>,
+  /// only listed for private messages
+  pub users: Vec<i64>,
+  pub current_user_attributes: CurrentUserAttributes,
+}
+
+#[derive(Serialize)]
+pub struct ApiMessage {
+  pub message_id: i64,
+  pub sender_id: i64,
+  pub channel_id: i64,
+  pub timestamp: String,
+  pub content: String,
+  pub is_action: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub sender: Option<ApiUser>,
+  /// echoed back to the client that sent the message, so it can replace its local copy
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub uuid: Option<String>,
+}
+
+impl ApiMessage {
+  pub fn new(message: &DbMessage, sender: Option<&User>, config: &Config) -> Self {
+    Self {
+      message_id: message.id,
+      sender_id: message.sender_id,
+      channel_id: message.channel_id,
+      timestamp: message.created_at.to_rfc3339(),
+      content: message.content.clone(),
+      is_action: message.is_action,
+      sender: sender.map(|sender| ApiUser::new(sender, config)),
+      uuid: None,
+    }
+  }
+
+  pub fn with_uuid(mut self, uuid: Option<String>) -> Self {
+    self.uuid = uuid;
+
+    self
+  }
+}
+
+/// Name of the private channel between two users, the same whichever of them asks
+fn pm_channel_name(user_id: i64, target_id: i64) -> String {
+  format!("#pm_{}-{}", user_id.min(target_id), user_id.max(target_id))
+}
+
+pub async fn fetch_channel(pool: &SqlitePool, channel_id: i64) -> sqlx::Result<Option<DbChannel>> {
+  sqlx::query_as::<_, DbChannel>("select * from channels where id = ?")
+    .bind(channel_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Channels with their latest message and how far `user_id` read them, limited to the ones `user_id` is in unless `public` is set
+async fn fetch_channel_states(pool: &SqlitePool, user_id: i64, channel_id: Option<i64>, public: bool) -> sqlx::Result<Vec<DbChannelState>> {
+  sqlx::query_as::<_, DbChannelState>(r#"
+    select
+      c.*,
+      (select max(id) from messages where channel_id = c.id) as last_message_id,
+      m.last_read_id
+    from channels c
+    left join channel_members m on m.channel_id = c.id and m.user_id = ?1
+    where (?2 is null or c.id = ?2)
+      and (m.user_id is not null or (?3 and c.type = 'PUBLIC'))
+    order by c.id
+  "#)
+    .bind(user_id)
+    .bind(channel_id)
+    .bind(public)
+    .fetch_all(pool)
+    .await
+}
+
+/// Public channels anyone can join
+pub async fn public_channels(pool: &SqlitePool, config: &Config, user: &User) -> sqlx::Result<Vec<ApiChannel>> {
+  let states = sqlx::query_as::<_, DbChannelState>(r#"
+    select
+      c.*,
+      (select max(id) from messages where channel_id = c.id) as last_message_id,
+      m.last_read_id
+    from channels c
+    left join channel_members m on m.channel_id = c.id and m.user_id = ?
+    where c.type = 'PUBLIC'
+    order by c.id
+  "#)
+    .bind(user.id)
+    .fetch_all(pool)
+    .await?;
+
+  api_channels(pool, config, user, states).await
+}
+
+/// Channels the user is in
+pub async fn joined_channels(pool: &SqlitePool, config: &Config, user: &User) -> sqlx::Result<Vec<ApiChannel>> {
+  let states = fetch_channel_states(pool, user.id, None, false).await?;
+
+  api_channels(pool, config, user, states).await
+}
+
+/// A single channel, if the user is in it or it's public
+pub async fn channel(pool: &SqlitePool, config: &Config, user: &User, channel_id: i64) -> sqlx::Result<Option<ApiChannel>> {
+  let states = fetch_channel_states(pool, user.id, Some(channel_id), true).await?;
+
+  Ok(api_channels(pool, config, user, states).await?.pop())
+}
+
+async fn api_channels(pool: &SqlitePool, config: &Config, user: &User, states: Vec<DbChannelState>) -> sqlx::Result<Vec<ApiChannel>> {
+  let mut channels = Vec::with_capacity(states.len());
+
+  for state in states {
+    let channel = state.channel;
+
+    let (name, icon, users) = if channel.channel_type == PM {
+      let users = members(pool, channel.id).await?;
+
+      // private channels are named after whoever is on the other end
+      match pm_target(pool, &channel, user.id).await? {
+        Some(target) => (target.username.clone(), Some(target.avatar_url(config)), users),
+        None => (channel.name.clone(), None, users),
+      }
+    } else {
+      (channel.name.clone(), None, vec![])
+    };
+
+    let can_message = can_send(pool, &channel, user).await?;
+
+    channels.push(ApiChannel {
+      channel_id: channel.id,
+      name,
+      description: channel.description.clone(),
+      icon,
+      channel_type: channel.channel_type.clone(),
+      moderated: channel.moderated,
+      last_message_id: state.last_message_id,
+      last_read_id: state.last_read_id,
+      users,
+      current_user_attributes: CurrentUserAttributes {
+        can_message: can_message.is_ok(),
+        can_message_error: can_message.err(),
+        last_read_id: state.last_read_id,
+      },
+    });
+  }
+
+  Ok(channels)
+}
+
+pub async fn members(pool: &SqlitePool, channel_id: i64) -> sqlx::Result<Vec<i64>> {
+  sqlx::query_scalar::<_, i64>(r#"
+    select user_id from channel_members
+    where channel_id = ?
+    order by user_id
+  "#)
+    .bind(channel_id)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn is_member(pool: &SqlitePool, channel_id: i64, user_id: i64) -> sqlx::Result<bool> {
+  sqlx::query_scalar::<_, bool>(r#"
+    select exists (
+      select 1 from channel_members
+      where channel_id = ? and user_id = ?
+    )
+  "#)
+    .bind(channel_id)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+}
+
+/// Adds the user to a channel, returning whether they weren't in it already
+pub async fn join(pool: &SqlitePool, channel_id: i64, user_id: i64) -> sqlx::Result<bool> {
+  let result = sqlx::query(r#"
+    insert into channel_members (channel_id, user_id)
+    values (?, ?)
+    on conflict (channel_id, user_id) do nothing
+  "#)
+    .bind(channel_id)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+  Ok(result.rows_affected() > 0)
+}
+
+/// Removes the user from a channel, returning whether they were in it
+pub async fn leave(pool: &SqlitePool, channel_id: i64, user_id: i64) -> sqlx::Result<bool> {
+  let result = sqlx::query(r#"
+    delete from channel_members
+    where channel_id = ? and user_id = ?
+  "#)
+    .bind(channel_id)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+  Ok(result.rows_affected() > 0)
+}
+
+/// Moves how far the user read a channel forward, it never goes back
+pub async fn mark_as_read(pool: &SqlitePool, channel_id: i64, user_id: i64, message_id: i64) -> sqlx::Result<()> {
+  sqlx::query(r#"
+    update channel_members
+    set last_read_id = max(coalesce(last_read_id, 0), (
+      select coalesce(max(id), 0) from messages
+      where channel_id = ?1 and id <= ?3
+    ))
+    where channel_id = ?1 and user_id = ?2
+  "#)
+    .bind(channel_id)
+    .bind(user_id)
+    .bind(message_id)
+    .execute(pool)
+    .await?;
+
+  Ok(())
+}
+
+/// The two users of a private channel, taken from its name
+fn pm_users(channel: &DbChannel) -> Option<[i64; 2]> {
+  let (a, b) = channel.name.strip_prefix("#pm_")?.split_once('-')?;
+
+  Some([a.parse().ok()?, b.parse().ok()?])
+}
+
+/// The other user of a private channel
+async fn pm_target(pool: &SqlitePool, channel: &DbChannel, user_id: i64) -> sqlx::Result<Option<User>> {
+  let Some(target_id) = pm_users(channel).and_then(|users| users.into_iter().find(|id| *id != user_id)) else {
+    return Ok(None);
+  };
+
+  sqlx::query_as::<_, User>("select * from users where id = ?")
+    .bind(target_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Finds the private channel between two users, creating it the first time they talk.
+///
+/// The user asking for it (re)joins it, whether they weren't in it already is returned alongside it.
+/// The target joins once a message is sent.
+pub async fn pm_channel(pool: &SqlitePool, user_id: i64, target_id: i64) -> sqlx::Result<(DbChannel, bool)> {
+  let channel = sqlx::query_as::<_, DbChannel>(r#"
+    insert into channels (name, type)
+    values (?, 'PM')
+    on conflict (name) do update set name = excluded.name
+    returning *
+  "#)
+    .bind(pm_channel_name(user_id, target_id))
+    .fetch_one(pool)
+    .await?;
+
+  let joined = join(pool, channel.id, user_id).await?;
+
+  Ok((channel, joined))
+}
+
+/// Whether a user is an admin or moderator
+pub async fn is_moderator(pool: &SqlitePool, user_id: i64) -> sqlx::Result<bool> {
+  sqlx::query_scalar::<_, bool>(r#"
+    select exists (
+      select 1 from user_groups ug
+      join groups g on g.id = ug.group_id
+      where ug.user_id = ? and g.identifier in ('admin', 'moderator')
+    )
+  "#)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+}
+
+/// Whether the user can send messages to a channel, with the reason when they can't
+pub async fn can_send(pool: &SqlitePool, channel: &DbChannel, user: &User) -> sqlx::Result<Result<(), &'static str>> {
+  if is_silenced(pool, user.id).await? {
+    return Ok(Err(SILENCED));
+  }
+
+  if channel.moderated && !is_moderator(pool, user.id).await? {
+    return Ok(Err("This channel is moderated."));
+  }
+
+  if channel.channel_type == PM {
+    let Some(target) = pm_target(pool, channel, user.id).await? else {
+      return Ok(Err("This user can't receive messages."));
+    };
+
+    if let Err(restriction) = relations::can_message(pool, user.id, target.id).await? {
+      return Ok(Err(restriction.message()));
+    }
+  }
+
+  Ok(Ok(()))
+}
+
+pub struct SentMessage {
+  pub message: DbMessage,
+  /// users that were put back in the channel to receive the message
+  pub joined: Vec<i64>,
+}
+
+/// Whether the content of a message can be sent, wherever it goes
+pub fn check_content(content: &str) -> Result<(), &'static str> {
+  let content = content.trim();
+
+  if content.is_empty() {
+    return Err("message is empty");
+  }
+
+  if content.chars().count() > MESSAGE_LENGTH_LIMIT {
+    return Err("message is too long");
+  }
+
+  Ok(())
+}
+
+/// Whether the message can be sent to a channel, the sender has to be in the channel
+pub async fn check_message(pool: &SqlitePool, channel: &DbChannel, sender: &User, content: &str) -> sqlx::Result<Result<(), &'static str>> {
+  if let Err(e) = check_content(content) {
+    return Ok(Err(e));
+  }
+
+  if !is_member(pool, channel.id, sender.id).await? {
+    return Ok(Err("you're not in this channel"));
+  }
+
+  can_send(pool, channel, sender).await
+}
+
+/// Stores a message sent to a channel, it should have passed [`check_message`] first.
+///
+/// Private channels are rejoined by the other user so the message shows up for them again.
+pub async fn send_message(pool: &SqlitePool, channel: &DbChannel, sender: &User, content: &str, is_action: bool) -> sqlx::Result<SentMessage> {
+  let content = content.trim();
+
+  let mut joined = vec![];
+
+  for user_id in pm_users(channel).into_iter().flatten() {
+    if join(pool, channel.id, user_id).await? {
+      joined.push(user_id);
+    }
+  }
+
+  let message = sqlx::query_as::<_, DbMessage>(r#"
+    insert into messages (channel_id, sender_id, content, is_action, created_at)
+    values (?, ?, ?, ?, ?)
+    returning *
+  "#)
+    .bind(channel.id)
+    .bind(sender.id)
+    .bind(content)
+    .bind(is_action)
+    .bind(Utc::now())
+    .fetch_one(pool)
+    .await?;
+
+  Ok(SentMessage { message, joined })
+}
+
+/// Messages of a channel with ids between `since` and `until`, oldest first.
+///
+/// Without `since` the latest messages are returned.
+pub async fn messages(pool: &SqlitePool, channel_id: i64, since: Option<i64>, until: Option<i64>, limit: i64) -> sqlx::Result<Vec<DbMessage>> {
+  let mut messages = sqlx::query_as::<_, DbMessage>(r#"
+    select * from messages
+    where channel_id = ?1 and (?2 is null or id > ?2) and (?3 is null or id < ?3)
+    order by case when ?2 is null then -id else id end
+    limit ?4
+  "#)
+    .bind(channel_id)
+    .bind(since)
+    .bind(until)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+  if since.is_none() {
+    messages.reverse();
+  }
+
+  Ok(messages)
+}
+
+/// Messages sent after `since` to any of the channels the user is in, oldest first
+pub async fn new_messages(pool: &SqlitePool, user_id: i64, since: i64, limit: i64) -> sqlx::Result<Vec<DbMessage>> {
+  sqlx::query_as::<_, DbMessage>(r#"
+    select msg.* from messages msg
+    join channel_members m on m.channel_id = msg.channel_id and m.user_id = ?
+    where msg.id > ?
+    order by msg.id
+    limit ?
+  "#)
+    .bind(user_id)
+    .bind(since)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+/// Attaches their senders to messages
+pub async fn api_messages(pool: &SqlitePool, config: &Config, messages: &[DbMessage]) -> sqlx::Result<Vec<ApiMessage>> {
+  let sender_ids = messages.iter().map(|message| message.sender_id).collect::<Vec<_>>();
+
+  let senders = sqlx::query_as::<_, User>(r#"
+    select * from users
+    where id in (select value from json_each(?))
+  "#)
+    .bind(serde_json::json!(sender_ids).to_string())
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|user| (user.id, user))
+    .collect::<HashMap<_, _>>();
+
+  Ok(messages.iter()
+    .map(|message| ApiMessage::new(message, senders.get(&message.sender_id), config))
+    .collect())
+}
+
+/// Messages recently sent by each user, to keep them from flooding chat
+#[derive(Default)]
+pub struct RateLimiter {
+  sent: Mutex<HashMap<i64, VecDeque<Instant>>>,
+}
+
+impl RateLimiter {
+  /// Records a message about to be sent, unless the user already sent too many recently
+  pub fn try_send(&self, user_id: i64) -> bool {
+    let mut sent = self.sent.lock().unwrap();
+    let now = Instant::now();
+
+    sent.retain(|_, times| times.back().is_some_and(|time| now.duration_since(*time) < RATE_LIMIT_WINDOW));
+
+    let times = sent.entry(user_id).or_default();
+
+    while times.front().is_some_and(|time| now.duration_since(*time) >= RATE_LIMIT_WINDOW) {
+      times.pop_front();
+    }
+
+    if times.len() >= RATE_LIMIT_MESSAGES {
+      return false;
+    }
+
+    times.push_back(now);
+
+    true
+  }
+}
+
+#[derive(Clone, FromRow)]
+pub struct DbSilence {
+  pub id: i64,
+  pub user_id: i64,
+  pub actor_id: Option<i64>,
+  pub reason: String,
+  pub created_at: DateTime<Utc>,
+  pub ends_at: DateTime<Utc>,
+}
+
+/// Silence as the client gets it, it hides the silenced user's messages
+#[derive(Serialize)]
+pub struct ApiSilence {
+  pub id: i64,
+  pub user_id: i64,
+}
+
+pub async fn is_silenced(pool: &SqlitePool, user_id: i64) -> sqlx::Result<bool> {
+  sqlx::query_scalar::<_, bool>(r#"
+    select exists (
+      select 1 from silences
+      where user_id = ? and ends_at > ?
+    )
+  "#)
+    .bind(user_id)
+    .bind(Utc::now())
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn silence(pool: &SqlitePool, user_id: i64, actor_id: Option<i64>, duration: TimeDelta, reason: &str) -> sqlx::Result<DbSilence> {
+  let now = Utc::now();
+
+  sqlx::query_as::<_, DbSilence>(r#"
+    insert into silences (user_id, actor_id, reason, created_at, ends_at)
+    values (?, ?, ?, ?, ?)
+    returning *
+  "#)
+    .bind(user_id)
+    .bind(actor_id)
+    .bind(reason)
+    .bind(now)
+    .bind(now + duration)
+    .fetch_one(pool)
+    .await
+}
+
+/// Ends the user's silences early, returning whether they were silenced
+pub async fn unsilence(pool: &SqlitePool, user_id: i64) -> sqlx::Result<bool> {
+  let now = Utc::now();
+
+  let result = sqlx::query(r#"
+    update silences
+    set ends_at = ?1
+    where user_id = ?2 and ends_at > ?1
+  "#)
+    .bind(now)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+  Ok(result.rows_affected() > 0)
+}
+
+/// Silences the client hasn't seen yet.
+///
+/// `history_since` is the last silence it knows about, otherwise the ones issued after the message `since` are returned.
+/// Without either it gets the silences that are still going on.
+pub async fn silences(pool: &SqlitePool, since: Option<i64>, history_since: Option<i64>) -> sqlx::Result<Vec<ApiSilence>> {
+  let silences = sqlx::query_as::<_, DbSilence>(r#"
+    select * from silences
+    where case
+      when ?1 is not null then id > ?1
+      when ?2 is not null then created_at > coalesce((select created_at from messages where id = ?2), ?3)
+      else ends_at > ?3
+    end
+    order by id
+    limit ?4
+  "#)
+    .bind(history_since)
+    .bind(since)
+    .bind(Utc::now())
+    .bind(SILENCE_LIMIT)
+    .fetch_all(pool)
+    .await?;
+
+  Ok(silences.iter()
+    .map(|silence| ApiSilence { id: silence.id, user_id: silence.user_id })
+    .collect())
+}
+
+/// Deletes a message, returning it if it existed
+pub async fn delete_message(pool: &SqlitePool, message_id: i64) -> sqlx::Result<Option<DbMessage>> {
+  sqlx::query_as::<_, DbMessage>(r#"
+    delete from messages
+    where id = ?
+    returning *
+  "#)
+    .bind(message_id)
+    .fetch_optional(pool)
+    .await
+}
\ No newline at end of file
@@ -0,0 +1,252 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use chrono::TimeDelta;
+
+use crate::{auth::{generate_secret, hash_secret, OAuthClient}, beatmaps::import::import_osz, chat, state::FiberState};
+
+const USAGE: &str = r#"usage:
+  fibers                                    run the server
+  fibers client create <name> [scopes...]   create an oauth client (scopes default to "public")
+  fibers client list                        list oauth clients
+  fibers client delete <id>                 delete an oauth client and its tokens
+  fibers import <path>...                   import .osz archives, or every archive in a directory, as ranked
+  fibers group add <username> <group>       add a user to a group (admin, moderator or bot)
+  fibers group remove <username> <group>    remove a user from a group
+  fibers silence <username> <duration> [reason...]
+                                            keep a user from chatting for a duration like 30m, 12h or 7d
+  fibers unsilence <username>               end a user's silence early"#;
+
+/// Runs an administrative subcommand instead of the server
+pub async fn run(state: &FiberState, args: &[String]) -> Result<()> {
+  let args = args.iter()
+    .map(String::as_str)
+    .collect::<Vec<_>>();
+
+  match args.as_slice() {
+    ["client", "create", name, scopes @ ..] => create_client(state, name, scopes).await,
+    ["client", "list"] => list_clients(state).await,
+    ["client", "delete", id] => delete_client(state, id.parse()?).await,
+    ["import", paths @ ..] if !paths.is_empty() => import(state, paths).await,
+    ["group", "add", username, group] => add_to_group(state, username, group).await,
+    ["group", "remove", username, group] => remove_from_group(state, username, group).await,
+    ["silence", username, duration, reason @ ..] => silence(state, username, duration, &reason.join(" ")).await,
+    ["unsilence", username] => unsilence(state, username).await,
+    _ => bail!(USAGE),
+  }
+}
+
+async fn create_client(state: &FiberState, name: &str, scopes: &[&str]) -> Result<()> {
+  let scopes = match scopes {
+    [] => "public".into(),
+    scopes => scopes.join(" "),
+  };
+
+  // only the hash is stored, so the secret can't be shown again later
+  let secret = generate_secret(40);
+
+  let client = sqlx::query_as::<_, OAuthClient>(r#"
+    insert into oauth_clients (name, secret_hash, scopes) values
+    (?, ?, ?)
+    returning *
+  "#)
+    .bind(name)
+    .bind(hash_secret(&secret))
+    .bind(scopes)
+    .fetch_one(&state.pool)
+    .await?;
+
+  println!("client_id:     {}", client.id);
+  println!("client_secret: {}", secret);
+  println!("scopes:        {}", client.scopes);
+
+  Ok(())
+}
+
+async fn list_clients(state: &FiberState) -> Result<()> {
+  let clients = sqlx::query_as::<_, OAuthClient>(r#"
+    select * from oauth_clients
+    order by id
+  "#)
+    .fetch_all(&state.pool)
+    .await?;
+
+  for client in clients {
+    println!("{}\t{}\t{}", client.id, client.name, client.scopes);
+  }
+
+  Ok(())
+}
+
+async fn delete_client(state: &FiberState, id: i64) -> Result<()> {
+  let result = sqlx::query(r#"
+    delete from oauth_clients
+    where id = ?
+  "#)
+    .bind(id)
+    .execute(&state.pool)
+    .await?;
+
+  if result.rows_affected() == 0 {
+    bail!("no client with id {}", id);
+  }
+
+  Ok(())
+}
+
+async fn import(state: &FiberState, paths: &[&str]) -> Result<()> {
+  let mut archives = vec![];
+
+  for path in paths {
+    let path = Path::new(path);
+
+    if !path.is_dir() {
+      archives.push(path.to_path_buf());
+      continue;
+    }
+
+    for entry in std::fs::read_dir(path)? {
+      let path = entry?.path();
+
+      if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("osz")) {
+        archives.push(path);
+      }
+    }
+  }
+
+  archives.sort();
+
+  let mut failed = 0;
+
+  for path in &archives {
+    let data = tokio::fs::read(path).await?;
+
+    // one broken archive shouldn't stop a whole folder from being imported
+    if let Err(e) = import_osz(state, data, 1).await {
+      eprintln!("failed to import {}: {:?}", path.display(), e);
+      failed += 1;
+    }
+  }
+
+  if failed > 0 {
+    bail!("{} of {} archives failed to import", failed, archives.len());
+  }
+
+  Ok(())
+}
+
+async fn find_user_and_group(state: &FiberState, username: &str, group: &str) -> Result<(i64, i64)> {
+  let Some(user_id) = sqlx::query_scalar::<_, i64>("select id from users where username = ?")
+    .bind(username)
+    .fetch_optional(&state.pool)
+    .await? else
+  {
+    bail!("no user named {}", username);
+  };
+
+  let Some(group_id) = sqlx::query_scalar::<_, i64>("select id from groups where identifier = ?")
+    .bind(group)
+    .fetch_optional(&state.pool)
+    .await? else
+  {
+    bail!("no group named {}", group);
+  };
+
+  Ok((user_id, group_id))
+}
+
+async fn add_to_group(state: &FiberState, username: &str, group: &str) -> Result<()> {
+  let (user_id, group_id) = find_user_and_group(state, username, group).await?;
+
+  sqlx::query(r#"
+    insert into user_groups (user_id, group_id) values
+    (?, ?)
+    on conflict do nothing
+  "#)
+    .bind(user_id)
+    .bind(group_id)
+    .execute(&state.pool)
+    .await?;
+
+  // bots get the flag the client checks as well
+  if group == "bot" {
+    sqlx::query("update users set is_bot = true where id = ?")
+      .bind(user_id)
+      .execute(&state.pool)
+      .await?;
+  }
+
+  Ok(())
+}
+
+async fn remove_from_group(state: &FiberState, username: &str, group: &str) -> Result<()> {
+  let (user_id, group_id) = find_user_and_group(state, username, group).await?;
+
+  sqlx::query(r#"
+    delete from user_groups
+    where user_id = ? and group_id = ?
+  "#)
+    .bind(user_id)
+    .bind(group_id)
+    .execute(&state.pool)
+    .await?;
+
+  if group == "bot" {
+    sqlx::query("update users set is_bot = false where id = ?")
+      .bind(user_id)
+      .execute(&state.pool)
+      .await?;
+  }
+
+  Ok(())
+}
+
+/// Parses durations like `30s`, `10m`, `2h` or `7d`
+fn parse_duration(duration: &str) -> Option<TimeDelta> {
+  let split = duration.find(|c: char| !c.is_ascii_digit())?;
+  let (amount, unit) = duration.split_at(split);
+  let amount = amount.parse::<i64>().ok().filter(|amount| *amount > 0)?;
+
+  match unit {
+    "s" => TimeDelta::try_seconds(amount),
+    "m" => TimeDelta::try_minutes(amount),
+    "h" => TimeDelta::try_hours(amount),
+    "d" => TimeDelta::try_days(amount),
+    _ => None,
+  }
+}
+
+async fn find_user(state: &FiberState, username: &str) -> Result<i64> {
+  let Some(user_id) = sqlx::query_scalar::<_, i64>("select id from users where username = ?")
+    .bind(username)
+    .fetch_optional(&state.pool)
+    .await? else
+  {
+    bail!("no user named {}", username);
+  };
+
+  Ok(user_id)
+}
+
+async fn silence(state: &FiberState, username: &str, duration: &str, reason: &str) -> Result<()> {
+  let Some(duration) = parse_duration(duration) else {
+    bail!("invalid duration {}, use something like 30m, 12h or 7d", duration);
+  };
+
+  let user_id = find_user(state, username).await?;
+  let silence = chat::silence(&state.pool, user_id, None, duration, reason).await?;
+
+  println!("silenced {} until {}", username, silence.ends_at);
+
+  Ok(())
+}
+
+async fn unsilence(state: &FiberState, username: &str) -> Result<()> {
+  let user_id = find_user(state, username).await?;
+
+  if !chat::unsilence(&state.pool, user_id).await? {
+    bail!("{} isn't silenced", username);
+  }
+
+  Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,64 @@
+use std::{env, path::PathBuf};
+
+/// Server configuration, read from `FIBERS_*` environment variables
+pub struct Config {
+  pub database_url: String,
+  /// where clients reach this server, used for links to files it serves
+  pub base_url: String,
+  /// websocket clients connect to for notifications and chat, `base_url` with a websocket scheme unless set
+  pub notification_endpoint: String,
+  /// where beatmap files and other uploads are stored
+  pub data_dir: PathBuf,
+  /// create an account on the fly when someone logs in with an unknown username
+  pub auto_register: bool,
+  /// require new logins to enter a code sent to the mail spool
+  pub session_verification: bool,
+  /// file outgoing mail is appended to, there's no smtp
+  pub mail_spool: PathBuf,
+}
+
+fn env_flag(name: &str, default: bool) -> bool {
+  match env::var(name) {
+    Ok(value) => matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"),
+    Err(_) => default,
+  }
+}
+
+impl Config {
+  /// Link to an image served from `/images`
+  pub fn image_url(&self, name: &str) -> String {
+    format!("{}/images/{}", self.base_url, name)
+  }
+
+  pub fn from_env() -> Self {
+    let base_url = env::var("FIBERS_BASE_URL")
+      .map(|url| url.trim_end_matches('/').to_string())
+      .unwrap_or_else(|_| "http://localhost:19991".into());
+
+    let notification_endpoint = env::var("FIBERS_NOTIFICATION_ENDPOINT")
+      .unwrap_or_else(|_| {
+        let url = match base_url.split_once("://") {
+          Some(("https", rest)) => format!("wss://{}", rest),
+          Some((_, rest)) => format!("ws://{}", rest),
+          None => format!("ws://{}", base_url),
+        };
+
+        format!("{}/notifications", url)
+      });
+
+    Self {
+      database_url: env::var("FIBERS_DATABASE_URL")
+        .unwrap_or_else(|_| "sqlite:fibers.db".into()),
+      base_url,
+      notification_endpoint,
+      data_dir: env::var("FIBERS_DATA_DIR")
+        .unwrap_or_else(|_| "data".into())
+        .into(),
+      auto_register: env_flag("FIBERS_AUTO_REGISTER", false),
+      session_verification: env_flag("FIBERS_SESSION_VERIFICATION", true),
+      mail_spool: env::var("FIBERS_MAIL_SPOOL")
+        .unwrap_or_else(|_| "mail.log".into())
+        .into(),
+    }
+  }
+}
\ No newline at end of file
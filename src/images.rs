@@ -0,0 +1,56 @@
+use std::io::Cursor;
+
+use anyhow::{bail, Result};
+use image::{imageops::FilterType, DynamicImage, ImageFormat, ImageReader, Limits};
+
+const AVATAR_SIZE: u32 = 256;
+
+/// images shown for users that haven't uploaded their own, bundled with the server
+pub const DEFAULT_AVATAR: &str = "default/avatar.png";
+pub const DEFAULT_COVER: &str = "default/cover.jpg";
+
+/// profile covers are shown this wide, at a fixed aspect ratio
+const COVER_WIDTH: u32 = 2400;
+const COVER_HEIGHT: u32 = 640;
+
+/// larger images are refused before they're decoded
+const MAX_DIMENSION: u32 = 8192;
+
+fn decode(data: &[u8]) -> Result<DynamicImage> {
+  let mut limits = Limits::default();
+  limits.max_image_width = Some(MAX_DIMENSION);
+  limits.max_image_height = Some(MAX_DIMENSION);
+
+  let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+
+  if !matches!(reader.format(), Some(ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif | ImageFormat::WebP)) {
+    bail!("unsupported image format");
+  }
+
+  reader.limits(limits);
+
+  Ok(reader.decode()?)
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>> {
+  let mut data = Cursor::new(vec![]);
+  image.write_to(&mut data, format)?;
+
+  Ok(data.into_inner())
+}
+
+/// Crops an uploaded avatar to a square and scales it to `AVATAR_SIZE`, re-encoded as png
+pub fn avatar(data: &[u8]) -> Result<Vec<u8>> {
+  let image = decode(data)?
+    .resize_to_fill(AVATAR_SIZE, AVATAR_SIZE, FilterType::Lanczos3);
+
+  encode(&image, ImageFormat::Png)
+}
+
+/// Crops an uploaded cover to the cover's aspect ratio, re-encoded as jpeg since covers are mostly photos
+pub fn cover(data: &[u8]) -> Result<Vec<u8>> {
+  let image = decode(data)?
+    .resize_to_fill(COVER_WIDTH, COVER_HEIGHT, FilterType::Lanczos3);
+
+  encode(&DynamicImage::ImageRgb8(image.into_rgb8()), ImageFormat::Jpeg)
+}
\ No newline at end of file
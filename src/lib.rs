@@ -1,5 +1,17 @@
 pub mod auth;
+pub mod beatmaps;
+pub mod chat;
+pub mod cli;
+pub mod config;
+pub mod images;
+pub mod mail;
 pub mod notifications;
+pub mod presence;
+pub mod relations;
+pub mod rooms;
 pub mod routes;
+pub mod ruleset;
+pub mod scores;
 pub mod signalr;
-pub mod state;
\ No newline at end of file
+pub mod state;
+pub mod storage;
\ No newline at end of file
@@ -0,0 +1,28 @@
+use anyhow::Result;
+use chrono::Utc;
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+use crate::config::Config;
+
+/// "Sends" an email by appending it to the local mail spool
+pub async fn send(config: &Config, to: &str, subject: &str, body: &str) -> Result<()> {
+  let mail = format!(
+    "Date: {}\nTo: {}\nSubject: {}\n\n{}\n\n",
+    Utc::now().to_rfc2822(),
+    to,
+    subject,
+    body,
+  );
+
+  let mut spool = OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(&config.mail_spool)
+    .await?;
+
+  spool.write_all(mail.as_bytes()).await?;
+
+  println!("[mail] {} -> {}", subject, to);
+
+  Ok(())
+}
\ No newline at end of file
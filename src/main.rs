@@ -1,9 +1,8 @@
 use std::sync::Arc;
 
 use anyhow::Result;
-use axum::{body::Bytes, extract::{Request, State}, routing::{get, post}, Json, RequestExt, Router};
-use fibers::{auth::User, notifications::notifications_upgrade, routes::{self, users::ApiUser}, state::{FiberState, FiberStateInner}};
-use serde::Serialize;
+use axum::{body::Bytes, extract::Request, RequestExt, Router};
+use fibers::{cli, routes, scores::statistics, state::FiberStateInner};
 use sqlx::migrate;
 use tokio::net::TcpListener;
 
@@ -22,18 +21,30 @@ async fn main() -> Result<()> {
     .run(&state.pool)
     .await?;
 
+  let args = std::env::args().skip(1).collect::<Vec<_>>();
+
+  if !args.is_empty() {
+    return cli::run(&state, &args).await;
+  }
+
+  tokio::spawn(statistics::take_snapshots(state.pool.clone()));
+
   let listener = TcpListener::bind("0.0.0.0:19991").await?;
 
   let app = Router::new()
-    .nest("/signalr", routes::signalr::router())
+    .nest("/signalr", routes::signalr::router(state.clone()))
     .nest("/oauth", routes::oauth::router())
+    .merge(routes::admin::router(state.clone()))
+    .merge(routes::beatmaps::router(state.clone()))
+    .merge(routes::chat::router(state.clone()))
+    .merge(routes::images::router(state.clone()))
+    .merge(routes::search::router(state.clone()))
+    .merge(routes::scores::router(state.clone()))
+    .merge(routes::notifications::router(state.clone()))
+    .merge(routes::oauth::tokens_router(state.clone()))
+    .merge(routes::relations::router(state.clone()))
+    .merge(routes::session::router(state.clone()))
     .merge(routes::users::router(state.clone()))
-    // do i even need registering?
-    // .route("/users", post(register))
-    .route("/api/v2/notifications", get(notifications))
-    .route("/api/v2/friends", get(friends))
-    .route("/api/v2/chat/ack", post(chat_ack))
-    .route("/notifications", get(notifications_upgrade))
     .fallback(fallback_handler)
     .with_state(state);
 
@@ -44,74 +55,6 @@ async fn main() -> Result<()> {
   Ok(())
 }
 
-// #[derive(Debug, TryFromMultipart)]
-// struct RegisterMultipart {
-//   #[form_data(field_name = "user[username]")]
-//   username: String,
-//   #[form_data(field_name = "user[user_email]")]
-//   email: String,
-//   #[form_data(field_name = "user[password]")]
-//   password: String,
-// }
-
-// async fn register(
-//   user: TypedMultipart<RegisterMultipart>,
-// ) {
-//   println!("{:?}", user);
-// }
-
-#[derive(Default, Serialize)]
-struct Notifications {
-  has_more: bool,
-  notifications: Vec<()>,
-  unread_count: usize,
-  notification_endpoint: String,
-}
-
-async fn notifications() -> Json<Notifications> {
-  Json(Notifications {
-    notification_endpoint: "ws://localhost:19991/notifications".into(),
-    ..Default::default()
-  })
-}
-
-#[derive(Serialize)]
-struct Relation {
-  target_id: i64,
-  relation_type: u8,
-  mutual: bool,
-  user: ApiUser,
-}
-
-async fn friends(
-  State(state): State<FiberState>,
-) -> Json<Vec<Relation>> {
-  let Ok(bot) = sqlx::query_as::<_, User>("select * from users where id = 1")
-    .fetch_one(&state.pool)
-    .await else
-  {
-    unreachable!("probably");
-  };
-
-  Json(vec![
-    Relation {
-      target_id: 1,
-      relation_type: 0,
-      mutual: true,
-      user: ApiUser::new(&bot)
-    }
-  ])
-}
-
-#[derive(Default, Serialize)]
-struct ChatAck {
-  silences: Vec<()>,
-}
-
-async fn chat_ack() -> Json<ChatAck> {
-  Json(ChatAck::default())
-}
-
 async fn fallback_handler(
   req: Request
 ) {
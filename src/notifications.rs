@@ -1,14 +1,177 @@
-use axum::{body::Body, extract::{ws::WebSocket, WebSocketUpgrade}, response::Response};
+use std::{collections::HashMap, sync::{atomic::{AtomicU64, Ordering}, Mutex}};
 
-pub async fn notifications_upgrade(ws: WebSocketUpgrade) -> Response<Body> {
-  ws.on_upgrade(notifications_ws)
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::prelude::FromRow;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::state::FiberState;
+
+/// Event sent over the notifications websocket
+#[derive(Serialize)]
+struct SocketMessage<'a, T> {
+  event: &'a str,
+  data: T,
+}
+
+struct Listener {
+  id: u64,
+  /// whether the client asked for chat events with `chat.start`
+  chat: bool,
+  sender: UnboundedSender<String>,
+}
+
+/// Open notification websockets of every user, a user can have several clients connected
+#[derive(Default)]
+pub struct NotificationConnections {
+  listeners: Mutex<HashMap<i64, Vec<Listener>>>,
+  next_id: AtomicU64,
 }
 
-async fn notifications_ws(mut ws: WebSocket) {
-  while let Some(Ok(msg)) = ws.recv().await {
-    println!("{:?}", msg);
-    // match msg {
+impl NotificationConnections {
+  /// Registers a new websocket, the events for it arrive on the returned receiver
+  pub fn register(&self, user_id: i64) -> (u64, UnboundedReceiver<String>) {
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    self.listeners.lock().unwrap()
+      .entry(user_id)
+      .or_default()
+      .push(Listener { id, chat: false, sender });
+
+    (id, receiver)
+  }
+
+  pub fn unregister(&self, user_id: i64, id: u64) {
+    let mut listeners = self.listeners.lock().unwrap();
+
+    if let Some(user_listeners) = listeners.get_mut(&user_id) {
+      user_listeners.retain(|listener| listener.id != id);
+
+      if user_listeners.is_empty() {
+        listeners.remove(&user_id);
+      }
+    }
+  }
+
+  pub fn set_chat(&self, user_id: i64, id: u64, chat: bool) {
+    let mut listeners = self.listeners.lock().unwrap();
+
+    if let Some(listener) = listeners.get_mut(&user_id).and_then(|listeners| listeners.iter_mut().find(|listener| listener.id == id)) {
+      listener.chat = chat;
+    }
+  }
+
+  fn send_to(&self, user_ids: &[i64], chat: bool, event: &str, data: &impl Serialize) {
+    let Ok(message) = serde_json::to_string(&SocketMessage { event, data }) else {
+      return
+    };
+
+    let listeners = self.listeners.lock().unwrap();
+
+    for listener in user_ids.iter().filter_map(|user_id| listeners.get(user_id)).flatten() {
+      if !chat || listener.chat {
+        let _ = listener.sender.send(message.clone());
+      }
+    }
+  }
 
-    // }
+  /// Stops sending chat events to every client of the user
+  pub fn end_chat(&self, user_id: i64) {
+    if let Some(listeners) = self.listeners.lock().unwrap().get_mut(&user_id) {
+      for listener in listeners {
+        listener.chat = false;
+      }
+    }
   }
+
+  /// Whether any of the user's clients is receiving chat
+  pub fn is_chatting(&self, user_id: i64) -> bool {
+    self.listeners.lock().unwrap()
+      .get(&user_id)
+      .is_some_and(|listeners| listeners.iter().any(|listener| listener.chat))
+  }
+
+  /// Sends an event to every client of the users
+  pub fn send(&self, user_ids: &[i64], event: &str, data: &impl Serialize) {
+    self.send_to(user_ids, false, event, data);
+  }
+
+  /// Sends a chat event to the clients of the users that started chat
+  pub fn send_chat(&self, user_ids: &[i64], event: &str, data: &impl Serialize) {
+    self.send_to(user_ids, true, event, data);
+  }
+}
+
+#[derive(Clone, FromRow)]
+pub struct DbNotification {
+  pub id: i64,
+  pub user_id: i64,
+  pub name: String,
+  pub object_type: String,
+  pub object_id: i64,
+  pub source_user_id: Option<i64>,
+  /// json encoded object
+  pub details: String,
+  pub is_read: bool,
+  pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct ApiNotification {
+  id: i64,
+  name: String,
+  created_at: String,
+  object_type: String,
+  object_id: i64,
+  source_user_id: Option<i64>,
+  is_read: bool,
+  details: Value,
+}
+
+impl ApiNotification {
+  pub fn new(notification: &DbNotification) -> Self {
+    Self {
+      id: notification.id,
+      name: notification.name.clone(),
+      created_at: notification.created_at.to_rfc3339(),
+      object_type: notification.object_type.clone(),
+      object_id: notification.object_id,
+      source_user_id: notification.source_user_id,
+      is_read: notification.is_read,
+      details: serde_json::from_str(&notification.details).unwrap_or_default(),
+    }
+  }
+}
+
+/// What a notification is about, `object_type` and `object_id` point at the thing it's for
+pub struct NewNotification<'a> {
+  pub name: &'a str,
+  pub object_type: &'a str,
+  pub object_id: i64,
+  pub source_user_id: Option<i64>,
+  pub details: Value,
+}
+
+/// Stores a notification for the user and pushes it to their connected clients
+pub async fn notify(state: &FiberState, user_id: i64, notification: NewNotification<'_>) -> sqlx::Result<()> {
+  let notification = sqlx::query_as::<_, DbNotification>(r#"
+    insert into notifications (user_id, name, object_type, object_id, source_user_id, details, created_at)
+    values (?, ?, ?, ?, ?, ?, ?)
+    returning *
+  "#)
+    .bind(user_id)
+    .bind(notification.name)
+    .bind(notification.object_type)
+    .bind(notification.object_id)
+    .bind(notification.source_user_id)
+    .bind(notification.details.to_string())
+    .bind(Utc::now())
+    .fetch_one(&state.pool)
+    .await?;
+
+  state.notifications.send(&[user_id], "new", &ApiNotification::new(&notification));
+
+  Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,29 @@
+use std::{collections::HashMap, sync::Mutex};
+
+/// Users with a notification socket or metadata hub connection open, a user can have several of them
+#[derive(Default)]
+pub struct OnlineUsers {
+  connections: Mutex<HashMap<i64, usize>>,
+}
+
+impl OnlineUsers {
+  pub fn connect(&self, user_id: i64) {
+    *self.connections.lock().unwrap().entry(user_id).or_default() += 1;
+  }
+
+  pub fn disconnect(&self, user_id: i64) {
+    let mut connections = self.connections.lock().unwrap();
+
+    if let Some(count) = connections.get_mut(&user_id) {
+      *count -= 1;
+
+      if *count == 0 {
+        connections.remove(&user_id);
+      }
+    }
+  }
+
+  pub fn is_online(&self, user_id: i64) -> bool {
+    self.connections.lock().unwrap().contains_key(&user_id)
+  }
+}
\ No newline at end of file
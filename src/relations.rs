@@ -0,0 +1,104 @@
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+/// How a user relates to another, stored as `relation_type` in `relations`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RelationType {
+  Friend,
+  Block,
+}
+
+impl RelationType {
+  pub fn from_id(id: i64) -> Option<Self> {
+    match id {
+      0 => Some(Self::Friend),
+      1 => Some(Self::Block),
+      _ => None,
+    }
+  }
+
+  pub fn id(self) -> i64 {
+    match self {
+      Self::Friend => 0,
+      Self::Block => 1,
+    }
+  }
+}
+
+/// Why a user can't interact with another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restriction {
+  /// either of them blocked the other
+  Blocked,
+  /// the target only accepts messages from friends
+  FriendsOnly,
+}
+
+impl Restriction {
+  /// Message lazer expects when an action is refused, it's matched on by the client
+  pub fn message(self) -> &'static str {
+    match self {
+      Self::Blocked => "Cannot perform action due to user being blocked.",
+      Self::FriendsOnly => "Cannot perform action because user has disabled non-friend communications.",
+    }
+  }
+}
+
+/// The relation `user_id` has with `target_id`, if any
+pub async fn relation(pool: &SqlitePool, user_id: i64, target_id: i64) -> sqlx::Result<Option<RelationType>> {
+  let relation_type = sqlx::query_scalar::<_, i64>(r#"
+    select relation_type from relations
+    where user_id = ? and target_id = ?
+  "#)
+    .bind(user_id)
+    .bind(target_id)
+    .fetch_optional(pool)
+    .await?;
+
+  Ok(relation_type.and_then(RelationType::from_id))
+}
+
+/// Whether either of the users blocked the other
+pub async fn is_blocked(pool: &SqlitePool, user_id: i64, target_id: i64) -> sqlx::Result<bool> {
+  sqlx::query_scalar::<_, bool>(r#"
+    select exists (
+      select 1 from relations
+      where relation_type = 1
+        and ((user_id = ?1 and target_id = ?2) or (user_id = ?2 and target_id = ?1))
+    )
+  "#)
+    .bind(user_id)
+    .bind(target_id)
+    .fetch_one(pool)
+    .await
+}
+
+/// Whether `user_id` can invite `target_id` to a multiplayer room, osu-server-spectator refuses the same users as private messages
+pub async fn can_invite(pool: &SqlitePool, user_id: i64, target_id: i64) -> sqlx::Result<Result<(), Restriction>> {
+  can_message(pool, user_id, target_id).await
+}
+
+/// Whether `user_id` can send private messages to `target_id`
+pub async fn can_message(pool: &SqlitePool, user_id: i64, target_id: i64) -> sqlx::Result<Result<(), Restriction>> {
+  if is_blocked(pool, user_id, target_id).await? {
+    return Ok(Err(Restriction::Blocked));
+  }
+
+  let friends_only = sqlx::query_scalar::<_, bool>("select pm_friends_only from users where id = ?")
+    .bind(target_id)
+    .fetch_optional(pool)
+    .await?
+    .unwrap_or_default();
+
+  if friends_only && relation(pool, target_id, user_id).await? != Some(RelationType::Friend) {
+    return Ok(Err(Restriction::FriendsOnly));
+  }
+
+  Ok(Ok(()))
+}
+
+/// Whether `viewer_id` gets to see if `user_id` is online, users that blocked someone appear offline to them
+pub async fn presence_visible(pool: &SqlitePool, viewer_id: i64, user_id: i64) -> sqlx::Result<bool> {
+  Ok(relation(pool, user_id, viewer_id).await? != Some(RelationType::Block))
+}
\ No newline at end of file
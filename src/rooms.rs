@@ -0,0 +1,21 @@
+use std::{collections::HashMap, sync::Mutex};
+
+/// The multiplayer room each user is in, a user is in one room at most
+#[derive(Default)]
+pub struct RoomMembers {
+  rooms: Mutex<HashMap<i64, i64>>,
+}
+
+impl RoomMembers {
+  pub fn join(&self, user_id: i64, room_id: i64) {
+    self.rooms.lock().unwrap().insert(user_id, room_id);
+  }
+
+  pub fn leave(&self, user_id: i64) {
+    self.rooms.lock().unwrap().remove(&user_id);
+  }
+
+  pub fn room_of(&self, user_id: i64) -> Option<i64> {
+    self.rooms.lock().unwrap().get(&user_id).copied()
+  }
+}
\ No newline at end of file
@@ -0,0 +1,42 @@
+use axum::{body::Bytes, extract::{DefaultBodyLimit, State}, http::StatusCode, middleware, routing::post, Json, Router};
+use axum_typed_multipart::{FieldData, TryFromMultipart, TypedMultipart};
+use serde_json::{json, Value};
+
+use crate::{auth, beatmaps::import::import_osz, state::FiberState};
+
+/// largest archive accepted over http, bigger sets can still be imported from the cli
+const MAX_ARCHIVE_SIZE: usize = 200 * 1024 * 1024;
+
+#[derive(TryFromMultipart)]
+struct ImportMultipart {
+  #[form_data(limit = "200MiB")]
+  file: FieldData<Bytes>,
+  /// beatmapset status, ranked when missing
+  status: Option<i64>,
+}
+
+async fn import_beatmapset(
+  State(state): State<FiberState>,
+  TypedMultipart(body): TypedMultipart<ImportMultipart>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+  let status = body.status.unwrap_or(1);
+
+  if !(-2..=4).contains(&status) {
+    return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(json!({ "error": "invalid status" }))));
+  }
+
+  match import_osz(&state, body.file.contents.to_vec(), status).await {
+    Ok(beatmapset_id) => Ok(Json(json!({ "beatmapset_id": beatmapset_id }))),
+    Err(e) => {
+      eprintln!("{:?}", e);
+      Err((StatusCode::UNPROCESSABLE_ENTITY, Json(json!({ "error": e.to_string() }))))
+    },
+  }
+}
+
+pub fn router(state: FiberState) -> Router<FiberState> {
+  Router::new()
+    .route("/api/v2/admin/beatmapsets/import", post(import_beatmapset))
+    .layer(DefaultBodyLimit::max(MAX_ARCHIVE_SIZE))
+    .layer(middleware::from_fn_with_state(state, auth::admin_middleware))
+}
\ No newline at end of file
@@ -0,0 +1,660 @@
+use std::collections::HashMap;
+
+use axum::{extract::{Path, Query, RawQuery, State}, http::{HeaderMap, StatusCode}, middleware, response::Response, routing::{get, post}, Json, Router};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::prelude::FromRow;
+
+use crate::{auth, beatmaps::{archive::no_video_archive, difficulty, status::BeatmapStatus}, routes::{attachment, query_list}, ruleset::Ruleset, state::FiberState};
+
+/// most beatmaps the batch endpoint returns at once
+const MAX_BATCH_SIZE: usize = 50;
+
+const SELECT_BEATMAPSETS: &str = r#"
+  select
+    s.*,
+    (select coalesce(max(b.bpm), 0) from beatmaps b where b.beatmapset_id = s.id) as bpm,
+    (
+      select count(*) from scores sc
+      join beatmaps b on b.id = sc.beatmap_id
+      where b.beatmapset_id = s.id
+    ) as play_count
+  from beatmapsets s
+"#;
+
+const SELECT_BEATMAPS: &str = r#"
+  select
+    b.*,
+    (select count(*) from scores sc where sc.beatmap_id = b.id) as playcount,
+    (select count(*) from scores sc where sc.beatmap_id = b.id and sc.passed) as passcount
+  from beatmaps b
+"#;
+
+#[derive(Clone, FromRow)]
+pub struct DbBeatmapset {
+  pub id: i64,
+  pub artist: String,
+  pub artist_unicode: Option<String>,
+  pub title: String,
+  pub title_unicode: Option<String>,
+  pub creator: String,
+  pub creator_id: i64,
+  pub source: String,
+  pub tags: String,
+  pub status: i64,
+  pub genre_id: i64,
+  pub language_id: i64,
+  pub has_video: bool,
+  pub has_storyboard: bool,
+  pub submitted_at: Option<DateTime<Utc>>,
+  pub ranked_at: Option<DateTime<Utc>>,
+  pub updated_at: Option<DateTime<Utc>>,
+  pub archive_hash: Option<String>,
+  pub bpm: f32,
+  pub play_count: u32,
+}
+
+#[derive(Clone, FromRow)]
+pub struct DbBeatmap {
+  pub id: i64,
+  pub beatmapset_id: i64,
+  pub version: String,
+  pub checksum: Option<String>,
+  pub filename: Option<String>,
+  pub ruleset_id: u32,
+  pub status: i64,
+  pub difficulty_rating: f32,
+  pub drain: f32,
+  pub cs: f32,
+  pub od: f32,
+  pub ar: f32,
+  pub bpm: f32,
+  pub total_length: u32,
+  pub hit_length: u32,
+  pub max_combo: u32,
+  pub count_circles: u32,
+  pub count_sliders: u32,
+  pub count_spinners: u32,
+  pub updated_at: Option<DateTime<Utc>>,
+  pub playcount: u32,
+  pub passcount: u32,
+}
+
+#[derive(Serialize)]
+struct Covers {
+  cover: String,
+  #[serde(rename = "cover@2x")]
+  cover_2x: String,
+  card: String,
+  #[serde(rename = "card@2x")]
+  card_2x: String,
+  list: String,
+  #[serde(rename = "list@2x")]
+  list_2x: String,
+  slimcover: String,
+  #[serde(rename = "slimcover@2x")]
+  slimcover_2x: String,
+}
+
+impl Covers {
+  /// Points at the official asset server, which has covers for every set imported with its original id
+  fn new(beatmapset_id: i64) -> Self {
+    let url = |name: &str| format!("https://assets.ppy.sh/beatmaps/{}/covers/{}.jpg", beatmapset_id, name);
+
+    Self {
+      cover: url("cover"),
+      cover_2x: url("cover@2x"),
+      card: url("card"),
+      card_2x: url("card@2x"),
+      list: url("list"),
+      list_2x: url("list@2x"),
+      slimcover: url("slimcover"),
+      slimcover_2x: url("slimcover@2x"),
+    }
+  }
+}
+
+#[derive(Serialize)]
+struct NamedId {
+  id: i64,
+  name: &'static str,
+}
+
+fn genre(id: i64) -> NamedId {
+  let name = match id {
+    0 => "Any",
+    2 => "Video Game",
+    3 => "Anime",
+    4 => "Rock",
+    5 => "Pop",
+    6 => "Other",
+    7 => "Novelty",
+    9 => "Hip Hop",
+    10 => "Electronic",
+    11 => "Metal",
+    12 => "Classical",
+    13 => "Folk",
+    14 => "Jazz",
+    _ => "Unspecified",
+  };
+
+  NamedId { id, name }
+}
+
+fn language(id: i64) -> NamedId {
+  let name = match id {
+    0 => "Any",
+    2 => "English",
+    3 => "Japanese",
+    4 => "Chinese",
+    5 => "Instrumental",
+    6 => "Korean",
+    7 => "French",
+    8 => "German",
+    9 => "Swedish",
+    10 => "Spanish",
+    11 => "Italian",
+    12 => "Russian",
+    13 => "Polish",
+    14 => "Other",
+    _ => "Unspecified",
+  };
+
+  NamedId { id, name }
+}
+
+#[derive(Serialize)]
+struct Availability {
+  download_disabled: bool,
+  more_information: Option<String>,
+}
+
+#[derive(Serialize)]
+struct NominationsSummary {
+  current: u32,
+  required: u32,
+}
+
+#[derive(Serialize)]
+pub struct ApiBeatmapset {
+  artist: String,
+  artist_unicode: String,
+  availability: Availability,
+  bpm: f32,
+  can_be_hyped: bool,
+  covers: Covers,
+  creator: String,
+  deleted_at: Option<String>,
+  discussion_enabled: bool,
+  discussion_locked: bool,
+  favourite_count: u32,
+  genre: NamedId,
+  has_favourited: bool,
+  hype: Option<()>,
+  id: i64,
+  is_scoreable: bool,
+  language: NamedId,
+  last_updated: Option<String>,
+  legacy_thread_url: Option<String>,
+  nominations_summary: NominationsSummary,
+  nsfw: bool,
+  offset: i32,
+  play_count: u32,
+  preview_url: String,
+  ranked: i64,
+  ranked_date: Option<String>,
+  ratings: [u32; 11],
+  source: String,
+  spotlight: bool,
+  status: BeatmapStatus,
+  storyboard: bool,
+  submitted_date: Option<String>,
+  tags: String,
+  title: String,
+  title_unicode: String,
+  track_id: Option<i64>,
+  user_id: i64,
+  video: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  beatmaps: Option<Vec<ApiBeatmap>>,
+}
+
+impl ApiBeatmapset {
+  pub fn new(set: &DbBeatmapset) -> Self {
+    let status = BeatmapStatus::from_id(set.status);
+
+    Self {
+      artist: set.artist.clone(),
+      artist_unicode: set.artist_unicode.clone().unwrap_or_else(|| set.artist.clone()),
+      availability: Availability {
+        download_disabled: set.archive_hash.is_none(),
+        more_information: None,
+      },
+      bpm: set.bpm,
+      can_be_hyped: false,
+      covers: Covers::new(set.id),
+      creator: set.creator.clone(),
+      deleted_at: None,
+      discussion_enabled: true,
+      discussion_locked: false,
+      favourite_count: 0,
+      genre: genre(set.genre_id),
+      has_favourited: false,
+      hype: None,
+      id: set.id,
+      is_scoreable: status.has_leaderboard(),
+      language: language(set.language_id),
+      last_updated: set.updated_at.map(|date| date.to_rfc3339()),
+      legacy_thread_url: None,
+      nominations_summary: NominationsSummary {
+        current: 0,
+        required: 2,
+      },
+      nsfw: false,
+      offset: 0,
+      play_count: set.play_count,
+      preview_url: format!("//b.ppy.sh/preview/{}.mp3", set.id),
+      ranked: status.id(),
+      ranked_date: set.ranked_at.map(|date| date.to_rfc3339()),
+      ratings: [0; 11],
+      source: set.source.clone(),
+      spotlight: false,
+      status,
+      storyboard: set.has_storyboard,
+      submitted_date: set.submitted_at.map(|date| date.to_rfc3339()),
+      tags: set.tags.clone(),
+      title: set.title.clone(),
+      title_unicode: set.title_unicode.clone().unwrap_or_else(|| set.title.clone()),
+      track_id: None,
+      user_id: set.creator_id,
+      video: set.has_video,
+      beatmaps: None,
+    }
+  }
+
+  pub fn with_beatmaps(mut self, set: &DbBeatmapset, beatmaps: &[DbBeatmap]) -> Self {
+    self.beatmaps = Some(beatmaps.iter()
+      .map(|beatmap| ApiBeatmap::new(beatmap, set))
+      .collect());
+
+    self
+  }
+}
+
+#[derive(Serialize)]
+struct Failtimes {
+  fail: Vec<u32>,
+  exit: Vec<u32>,
+}
+
+#[derive(Serialize)]
+pub struct ApiBeatmap {
+  accuracy: f32,
+  ar: f32,
+  beatmapset_id: i64,
+  bpm: f32,
+  checksum: Option<String>,
+  convert: bool,
+  count_circles: u32,
+  count_sliders: u32,
+  count_spinners: u32,
+  cs: f32,
+  deleted_at: Option<String>,
+  difficulty_rating: f32,
+  drain: f32,
+  failtimes: Failtimes,
+  hit_length: u32,
+  id: i64,
+  is_scoreable: bool,
+  last_updated: Option<String>,
+  max_combo: u32,
+  mode: Ruleset,
+  mode_int: u32,
+  passcount: u32,
+  playcount: u32,
+  ranked: i64,
+  status: BeatmapStatus,
+  total_length: u32,
+  url: String,
+  user_id: i64,
+  version: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  beatmapset: Option<ApiBeatmapset>,
+}
+
+impl ApiBeatmap {
+  pub fn new(beatmap: &DbBeatmap, set: &DbBeatmapset) -> Self {
+    let status = BeatmapStatus::from_id(beatmap.status);
+    let ruleset = Ruleset::from_id(beatmap.ruleset_id).unwrap_or(Ruleset::Osu);
+
+    Self {
+      accuracy: beatmap.od,
+      ar: beatmap.ar,
+      beatmapset_id: beatmap.beatmapset_id,
+      bpm: beatmap.bpm,
+      checksum: beatmap.checksum.clone(),
+      convert: false,
+      count_circles: beatmap.count_circles,
+      count_sliders: beatmap.count_sliders,
+      count_spinners: beatmap.count_spinners,
+      cs: beatmap.cs,
+      deleted_at: None,
+      difficulty_rating: beatmap.difficulty_rating,
+      drain: beatmap.drain,
+      failtimes: Failtimes {
+        fail: vec![0; 100],
+        exit: vec![0; 100],
+      },
+      hit_length: beatmap.hit_length,
+      id: beatmap.id,
+      is_scoreable: status.has_leaderboard(),
+      last_updated: beatmap.updated_at.map(|date| date.to_rfc3339()),
+      max_combo: beatmap.max_combo,
+      mode: ruleset,
+      mode_int: ruleset.id(),
+      passcount: beatmap.passcount,
+      playcount: beatmap.playcount,
+      ranked: status.id(),
+      status,
+      total_length: beatmap.total_length,
+      url: format!("/beatmapsets/{}#{}/{}", beatmap.beatmapset_id, ruleset.name(), beatmap.id),
+      user_id: set.creator_id,
+      version: beatmap.version.clone(),
+      beatmapset: None,
+    }
+  }
+
+  /// Includes the set the beatmap belongs to, the way single beatmap lookups return it
+  fn with_beatmapset(mut self, set: &DbBeatmapset) -> Self {
+    self.beatmapset = Some(ApiBeatmapset::new(set));
+
+    self
+  }
+}
+
+pub async fn fetch_beatmapset(state: &FiberState, id: i64) -> sqlx::Result<Option<DbBeatmapset>> {
+  sqlx::query_as::<_, DbBeatmapset>(&format!("{} where s.id = ?", SELECT_BEATMAPSETS))
+    .bind(id)
+    .fetch_optional(&state.pool)
+    .await
+}
+
+pub async fn fetch_beatmap(state: &FiberState, id: i64) -> sqlx::Result<Option<DbBeatmap>> {
+  sqlx::query_as::<_, DbBeatmap>(&format!("{} where b.id = ?", SELECT_BEATMAPS))
+    .bind(id)
+    .fetch_optional(&state.pool)
+    .await
+}
+
+/// Beatmaps with any of the ids, by id
+pub async fn fetch_beatmaps(state: &FiberState, ids: &[i64]) -> sqlx::Result<HashMap<i64, DbBeatmap>> {
+  let beatmaps = sqlx::query_as::<_, DbBeatmap>(&format!("{} where b.id in (select value from json_each(?))", SELECT_BEATMAPS))
+    .bind(json!(ids).to_string())
+    .fetch_all(&state.pool)
+    .await?;
+
+  Ok(beatmaps.into_iter().map(|beatmap| (beatmap.id, beatmap)).collect())
+}
+
+/// Beatmapsets with any of the ids, by id
+pub async fn fetch_beatmapsets(state: &FiberState, ids: &[i64]) -> sqlx::Result<HashMap<i64, DbBeatmapset>> {
+  let sets = sqlx::query_as::<_, DbBeatmapset>(&format!("{} where s.id in (select value from json_each(?))", SELECT_BEATMAPSETS))
+    .bind(json!(ids).to_string())
+    .fetch_all(&state.pool)
+    .await?;
+
+  Ok(sets.into_iter().map(|set| (set.id, set)).collect())
+}
+
+pub async fn fetch_beatmaps_of_set(state: &FiberState, beatmapset_id: i64) -> sqlx::Result<Vec<DbBeatmap>> {
+  sqlx::query_as::<_, DbBeatmap>(&format!("{} where b.beatmapset_id = ? order by b.ruleset_id, b.difficulty_rating, b.id", SELECT_BEATMAPS))
+    .bind(beatmapset_id)
+    .fetch_all(&state.pool)
+    .await
+}
+
+/// Returns a beatmap along with its set
+async fn beatmap_response(state: &FiberState, beatmap: Option<DbBeatmap>) -> Result<Json<ApiBeatmap>, StatusCode> {
+  let beatmap = beatmap.ok_or(StatusCode::NOT_FOUND)?;
+
+  let set = fetch_beatmapset(state, beatmap.beatmapset_id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+  Ok(Json(ApiBeatmap::new(&beatmap, &set).with_beatmapset(&set)))
+}
+
+async fn beatmapset_response(state: &FiberState, id: i64) -> Result<Json<ApiBeatmapset>, StatusCode> {
+  let set = fetch_beatmapset(state, id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+  let beatmaps = fetch_beatmaps_of_set(state, id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+  Ok(Json(ApiBeatmapset::new(&set).with_beatmaps(&set, &beatmaps)))
+}
+
+#[derive(Deserialize)]
+struct BeatmapLookupQuery {
+  id: Option<i64>,
+  checksum: Option<String>,
+  filename: Option<String>,
+}
+
+/// Looks a beatmap up by the first of `id`, `checksum` or `filename` that's given
+async fn lookup_beatmap(
+  State(state): State<FiberState>,
+  Query(query): Query<BeatmapLookupQuery>,
+) -> Result<Json<ApiBeatmap>, StatusCode> {
+  let (column, value) = match (query.id, query.checksum, query.filename) {
+    (Some(id), _, _) => ("id", id.to_string()),
+    (None, Some(checksum), _) => ("checksum", checksum),
+    (None, None, Some(filename)) => ("filename", filename),
+    (None, None, None) => return Err(StatusCode::UNPROCESSABLE_ENTITY),
+  };
+
+  let beatmap = sqlx::query_as::<_, DbBeatmap>(&format!("{} where b.{} = ?", SELECT_BEATMAPS, column))
+    .bind(value)
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+  beatmap_response(&state, beatmap).await
+}
+
+async fn get_beatmap(
+  State(state): State<FiberState>,
+  Path(id): Path<i64>,
+) -> Result<Json<ApiBeatmap>, StatusCode> {
+  let beatmap = fetch_beatmap(&state, id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+  beatmap_response(&state, beatmap).await
+}
+
+#[derive(Serialize)]
+struct BeatmapsResponse {
+  beatmaps: Vec<ApiBeatmap>,
+}
+
+/// Batch lookup with `ids[]=1&ids[]=2`, unknown ids are left out
+async fn get_beatmaps(
+  State(state): State<FiberState>,
+  RawQuery(query): RawQuery,
+) -> Result<Json<BeatmapsResponse>, StatusCode> {
+  let ids = query_list(query.as_deref().unwrap_or_default(), "ids")
+    .into_iter()
+    .filter_map(|id| id.parse::<i64>().ok())
+    .take(MAX_BATCH_SIZE);
+
+  let mut beatmaps = vec![];
+
+  for id in ids {
+    let Some(beatmap) = fetch_beatmap(&state, id).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)? else {
+      continue
+    };
+
+    let Some(set) = fetch_beatmapset(&state, beatmap.beatmapset_id).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)? else {
+      continue
+    };
+
+    beatmaps.push(ApiBeatmap::new(&beatmap, &set).with_beatmapset(&set));
+  }
+
+  Ok(Json(BeatmapsResponse { beatmaps }))
+}
+
+async fn get_beatmapset(
+  State(state): State<FiberState>,
+  Path(id): Path<i64>,
+) -> Result<Json<ApiBeatmapset>, StatusCode> {
+  beatmapset_response(&state, id).await
+}
+
+#[derive(Deserialize)]
+struct BeatmapsetLookupQuery {
+  beatmap_id: i64,
+}
+
+/// Finds the set a beatmap belongs to
+async fn lookup_beatmapset(
+  State(state): State<FiberState>,
+  Query(query): Query<BeatmapsetLookupQuery>,
+) -> Result<Json<ApiBeatmapset>, StatusCode> {
+  let beatmap = fetch_beatmap(&state, query.beatmap_id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+  beatmapset_response(&state, beatmap.beatmapset_id).await
+}
+
+#[derive(Deserialize)]
+struct DownloadQuery {
+  #[serde(rename = "noVideo")]
+  no_video: Option<String>,
+}
+
+async fn download_beatmapset(
+  State(state): State<FiberState>,
+  Path(id): Path<i64>,
+  Query(query): Query<DownloadQuery>,
+  headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+  let set = fetch_beatmapset(&state, id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+  let Some(archive_hash) = &set.archive_hash else {
+    return Err(StatusCode::NOT_FOUND)
+  };
+
+  let no_video = matches!(query.no_video.as_deref(), Some("1" | "true")) && set.has_video;
+
+  let hash = match no_video {
+    true => no_video_archive(&state, id)
+      .await
+      .map_err(|e| {
+        eprintln!("{:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+      })?,
+    false => archive_hash.clone(),
+  };
+
+  let data = state.storage.get(&hash)
+    .await
+    .map_err(|e| {
+      eprintln!("{:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+  let filename = format!(
+    "{} {} - {}{}.osz",
+    set.id,
+    set.artist,
+    set.title,
+    if no_video { " [no video]" } else { "" },
+  );
+
+  Ok(attachment(&headers, data, "application/x-osu-beatmap-archive", &filename))
+}
+
+#[derive(Deserialize)]
+struct AttributesRequest {
+  /// acronyms, `APIMod` objects or a legacy bitmask
+  #[serde(default)]
+  mods: Value,
+  ruleset: Option<String>,
+  ruleset_id: Option<u32>,
+}
+
+impl AttributesRequest {
+  /// Mods in the `APIMod` format, whichever way they were sent
+  fn mods(&self) -> Vec<Value> {
+    match &self.mods {
+      Value::Array(mods) => mods.iter()
+        .map(|m| match m {
+          Value::String(acronym) => json!({ "acronym": acronym }),
+          m => m.clone(),
+        })
+        .collect(),
+      Value::Number(bits) => rosu_mods::GameModsIntermode::from_bits(bits.as_u64().unwrap_or_default() as u32)
+        .iter()
+        .map(|m| json!({ "acronym": m.acronym().as_str() }))
+        .collect(),
+      _ => vec![],
+    }
+  }
+}
+
+/// Difficulty attributes of a beatmap played in a ruleset with mods
+async fn beatmap_attributes(
+  State(state): State<FiberState>,
+  Path(id): Path<i64>,
+  Json(body): Json<AttributesRequest>,
+) -> Result<Json<Value>, StatusCode> {
+  let beatmap = fetch_beatmap(&state, id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+  let ruleset = match (&body.ruleset, body.ruleset_id) {
+    (Some(name), _) => Ruleset::from_name(name),
+    (None, Some(id)) => Ruleset::from_id(id),
+    (None, None) => Ruleset::from_id(beatmap.ruleset_id),
+  }.ok_or(StatusCode::UNPROCESSABLE_ENTITY)?;
+
+  // only osu! maps can be converted to other rulesets
+  if beatmap.ruleset_id != Ruleset::Osu.id() && beatmap.ruleset_id != ruleset.id() {
+    return Err(StatusCode::UNPROCESSABLE_ENTITY);
+  }
+
+  let attributes = difficulty::beatmap_attributes(&state, id, ruleset, body.mods())
+    .await
+    .map_err(|e| {
+      eprintln!("failed to calculate the difficulty of beatmap {}: {:?}", id, e);
+      StatusCode::UNPROCESSABLE_ENTITY
+    })?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+  Ok(Json(json!({ "attributes": difficulty::attributes_json(&attributes) })))
+}
+
+pub fn router(state: FiberState) -> Router<FiberState> {
+  Router::new()
+    .route("/api/v2/beatmaps", get(get_beatmaps))
+    .route("/api/v2/beatmaps/lookup", get(lookup_beatmap))
+    .route("/api/v2/beatmaps/{id}", get(get_beatmap))
+    .route("/api/v2/beatmaps/{id}/attributes", post(beatmap_attributes))
+    .route("/api/v2/beatmapsets/lookup", get(lookup_beatmapset))
+    .route("/api/v2/beatmapsets/{id}", get(get_beatmapset))
+    .route("/api/v2/beatmapsets/{id}/download", get(download_beatmapset))
+    .layer(middleware::from_fn_with_state(state, auth::public_middleware))
+}
\ No newline at end of file
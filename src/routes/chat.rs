@@ -0,0 +1,555 @@
+use axum::{extract::{Path, Query, RawQuery, State}, http::StatusCode, middleware, response::{IntoResponse, Response}, routing::{delete, get, post, put}, Extension, Json, Router};
+use axum_typed_multipart::{TryFromMultipart, TypedMultipart, TypedMultipartError};
+use chrono::TimeDelta;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{auth::{self, User}, chat::{self, ApiChannel, ApiMessage, ApiSilence, DbChannel, SentMessage, MESSAGE_LIMIT, PM, PUBLIC}, notifications::{self, NewNotification}, relations, routes::{error, query_list, users::ApiUser}, state::FiberState};
+
+/// longest a moderator can silence someone for, in seconds
+const MAX_SILENCE_DURATION: i64 = 365 * 24 * 60 * 60;
+
+/// private message notifications only show the start of the message
+const NOTIFICATION_TITLE_LENGTH: usize = 36;
+
+#[derive(TryFromMultipart)]
+struct MessageMultipart {
+  message: String,
+  is_action: Option<bool>,
+  uuid: Option<String>,
+}
+
+#[derive(TryFromMultipart)]
+struct NewPmMultipart {
+  target_id: i64,
+  message: String,
+  is_action: Option<bool>,
+  uuid: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MessagesQuery {
+  since: Option<i64>,
+  until: Option<i64>,
+  limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct UpdatesQuery {
+  since: Option<i64>,
+  history_since: Option<i64>,
+}
+
+#[derive(Default, Deserialize, TryFromMultipart)]
+struct AckParams {
+  since: Option<i64>,
+  history_since: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct SilenceRequest {
+  /// in seconds
+  duration: i64,
+  #[serde(default)]
+  reason: String,
+}
+
+#[derive(Serialize)]
+struct ChannelResponse {
+  channel: ApiChannel,
+  users: Vec<ApiUser>,
+}
+
+#[derive(Serialize)]
+struct NewPmResponse {
+  channel: ApiChannel,
+  message: ApiMessage,
+  new_channel_id: i64,
+}
+
+#[derive(Serialize)]
+struct Updates {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  messages: Option<Vec<ApiMessage>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  presence: Option<Vec<ApiChannel>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  silences: Option<Vec<ApiSilence>>,
+}
+
+#[derive(Serialize)]
+struct ChatAck {
+  silences: Vec<ApiSilence>,
+}
+
+/// Tells the user's clients that they're in a channel now
+async fn notify_join(state: &FiberState, user_id: i64, channel_id: i64) -> sqlx::Result<()> {
+  let Some(user) = sqlx::query_as::<_, User>("select * from users where id = ?")
+    .bind(user_id)
+    .fetch_optional(&state.pool)
+    .await? else
+  {
+    return Ok(())
+  };
+
+  if let Some(channel) = chat::channel(&state.pool, &state.config, &user, channel_id).await? {
+    state.notifications.send_chat(&[user.id], "chat.channel.join", &channel);
+  }
+
+  Ok(())
+}
+
+/// Delivers a new message to everyone in its channel, after telling the users it brought back into the channel
+async fn deliver(state: &FiberState, channel: &DbChannel, sent: &SentMessage, sender: &User) -> sqlx::Result<()> {
+  for user_id in &sent.joined {
+    notify_join(state, *user_id, sent.message.channel_id).await?;
+  }
+
+  let members = chat::members(&state.pool, sent.message.channel_id).await?;
+
+  state.notifications.send_chat(&members, "chat.message.new", &json!({
+    "messages": [ApiMessage::new(&sent.message, Some(sender), &state.config)],
+    "users": [ApiUser::new(sender, &state.config)],
+  }));
+
+  // private messages to someone who isn't around are kept as a notification
+  if channel.channel_type == PM {
+    for user_id in members.into_iter().filter(|user_id| *user_id != sender.id && !state.notifications.is_chatting(*user_id)) {
+      let notification = NewNotification {
+        name: "channel_message",
+        object_type: "channel",
+        object_id: channel.id,
+        source_user_id: Some(sender.id),
+        details: json!({
+          "title": sent.message.content.chars().take(NOTIFICATION_TITLE_LENGTH).collect::<String>(),
+          "type": "pm",
+          "username": sender.username,
+          "cover_url": sender.avatar_url(&state.config),
+        }),
+      };
+
+      if let Err(e) = notifications::notify(state, user_id, notification).await {
+        eprintln!("failed to send notification: {:?}", e);
+      }
+    }
+  }
+
+  Ok(())
+}
+
+async fn channels(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+) -> Result<Json<Vec<ApiChannel>>, StatusCode> {
+  let channels = chat::public_channels(&state.pool, &state.config, &user)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+  Ok(Json(channels))
+}
+
+async fn get_channel(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Path(channel_id): Path<i64>,
+) -> Result<Json<ChannelResponse>, Response> {
+  let channel = chat::channel(&state.pool, &state.config, &user, channel_id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+    .ok_or_else(|| error(StatusCode::NOT_FOUND, "channel not found"))?;
+
+  let users = sqlx::query_as::<_, User>(r#"
+    select * from users
+    where id in (select value from json_each(?))
+  "#)
+    .bind(json!(channel.users).to_string())
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  Ok(Json(ChannelResponse {
+    channel,
+    users: users.iter().map(|user| ApiUser::new(user, &state.config)).collect(),
+  }))
+}
+
+/// Joins a public channel, users can only add themselves
+async fn join_channel(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Path((channel_id, user_id)): Path<(i64, i64)>,
+) -> Result<Json<ApiChannel>, Response> {
+  if user_id != user.id {
+    return Err(error(StatusCode::FORBIDDEN, "you can only join channels yourself"));
+  }
+
+  let channel = chat::fetch_channel(&state.pool, channel_id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+    .filter(|channel| channel.channel_type == PUBLIC)
+    .ok_or_else(|| error(StatusCode::NOT_FOUND, "channel not found"))?;
+
+  let joined = chat::join(&state.pool, channel.id, user.id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  let channel = chat::channel(&state.pool, &state.config, &user, channel.id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+    .ok_or_else(|| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  // the user's other clients learn about it too
+  if joined {
+    state.notifications.send_chat(&[user.id], "chat.channel.join", &channel);
+  }
+
+  Ok(Json(channel))
+}
+
+async fn leave_channel(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Path((channel_id, user_id)): Path<(i64, i64)>,
+) -> Result<StatusCode, Response> {
+  if user_id != user.id {
+    return Err(error(StatusCode::FORBIDDEN, "you can only leave channels yourself"));
+  }
+
+  // looked up before leaving, private channels can't be seen from outside
+  let channel = chat::channel(&state.pool, &state.config, &user, channel_id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  let left = chat::leave(&state.pool, channel_id, user.id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  if let (true, Some(channel)) = (left, channel) {
+    state.notifications.send_chat(&[user.id], "chat.channel.part", &channel);
+  }
+
+  Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_messages(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Path(channel_id): Path<i64>,
+  Query(query): Query<MessagesQuery>,
+) -> Result<Json<Vec<ApiMessage>>, Response> {
+  let is_member = chat::is_member(&state.pool, channel_id, user.id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  if !is_member {
+    return Err(error(StatusCode::NOT_FOUND, "channel not found"));
+  }
+
+  let limit = query.limit.unwrap_or(MESSAGE_LIMIT).clamp(1, MESSAGE_LIMIT);
+
+  let messages = chat::messages(&state.pool, channel_id, query.since, query.until, limit)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  let messages = chat::api_messages(&state.pool, &state.config, &messages)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  Ok(Json(messages))
+}
+
+async fn send_message(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Path(channel_id): Path<i64>,
+  TypedMultipart(body): TypedMultipart<MessageMultipart>,
+) -> Result<Json<ApiMessage>, Response> {
+  let channel = chat::fetch_channel(&state.pool, channel_id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+    .ok_or_else(|| error(StatusCode::NOT_FOUND, "channel not found"))?;
+
+  chat::check_message(&state.pool, &channel, &user, &body.message)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+    .map_err(|e| error(StatusCode::UNPROCESSABLE_ENTITY, e))?;
+
+  if !state.chat_limits.try_send(user.id) {
+    return Err(error(StatusCode::TOO_MANY_REQUESTS, "you're sending messages too quickly"));
+  }
+
+  let sent = chat::send_message(&state.pool, &channel, &user, &body.message, body.is_action.unwrap_or_default())
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  // the message is stored already, failing here would only make the client send it again
+  if let Err(e) = deliver(&state, &channel, &sent, &user).await {
+    eprintln!("failed to deliver message {}: {:?}", sent.message.id, e);
+  }
+
+  Ok(Json(ApiMessage::new(&sent.message, Some(&user), &state.config).with_uuid(body.uuid)))
+}
+
+/// Starts a conversation with another user, or continues the one they already have
+async fn new_pm(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  TypedMultipart(body): TypedMultipart<NewPmMultipart>,
+) -> Result<Json<NewPmResponse>, Response> {
+  if body.target_id == user.id {
+    return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "you can't message yourself"));
+  }
+
+  let exists = sqlx::query_scalar::<_, bool>("select exists (select 1 from users where id = ?)")
+    .bind(body.target_id)
+    .fetch_one(&state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  if !exists {
+    return Err(error(StatusCode::NOT_FOUND, "user not found"));
+  }
+
+  let silenced = chat::is_silenced(&state.pool, user.id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  if silenced {
+    return Err(error(StatusCode::FORBIDDEN, chat::SILENCED));
+  }
+
+  // checked before the channel is created, so refused conversations don't leave an empty one behind
+  if let Err(restriction) = relations::can_message(&state.pool, user.id, body.target_id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+  {
+    return Err(error(StatusCode::FORBIDDEN, restriction.message()));
+  }
+
+  chat::check_content(&body.message)
+    .map_err(|e| error(StatusCode::UNPROCESSABLE_ENTITY, e))?;
+
+  if !state.chat_limits.try_send(user.id) {
+    return Err(error(StatusCode::TOO_MANY_REQUESTS, "you're sending messages too quickly"));
+  }
+
+  let (channel, joined) = chat::pm_channel(&state.pool, user.id, body.target_id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  let sent = chat::send_message(&state.pool, &channel, &user, &body.message, body.is_action.unwrap_or_default())
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  if joined && let Err(e) = notify_join(&state, user.id, channel.id).await {
+    eprintln!("failed to notify user {} of joining channel {}: {:?}", user.id, channel.id, e);
+  }
+
+  // the message is stored already, failing here would only make the client send it again
+  if let Err(e) = deliver(&state, &channel, &sent, &user).await {
+    eprintln!("failed to deliver message {}: {:?}", sent.message.id, e);
+  }
+
+  let api_channel = chat::channel(&state.pool, &state.config, &user, channel.id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+    .ok_or_else(|| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  Ok(Json(NewPmResponse {
+    channel: api_channel,
+    message: ApiMessage::new(&sent.message, Some(&user), &state.config).with_uuid(body.uuid),
+    new_channel_id: channel.id,
+  }))
+}
+
+async fn mark_as_read(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Path((channel_id, message_id)): Path<(i64, i64)>,
+) -> Result<StatusCode, StatusCode> {
+  chat::mark_as_read(&state.pool, channel_id, user.id, message_id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+  Ok(StatusCode::NO_CONTENT)
+}
+
+/// Channels the user is in and messages sent to them after `since`, `includes[]` picks which of them are sent
+async fn updates(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Query(query): Query<UpdatesQuery>,
+  RawQuery(raw_query): RawQuery,
+) -> Result<Json<Updates>, StatusCode> {
+  let includes = query_list(raw_query.as_deref().unwrap_or_default(), "includes");
+  let include = |key: &str| includes.is_empty() || includes.iter().any(|include| include == key);
+
+  let presence = match include("presence") {
+    true => Some(
+      chat::joined_channels(&state.pool, &state.config, &user)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    ),
+    false => None,
+  };
+
+  let messages = match include("messages") {
+    true => {
+      let messages = chat::new_messages(&state.pool, user.id, query.since.unwrap_or_default(), MESSAGE_LIMIT)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+      Some(
+        chat::api_messages(&state.pool, &state.config, &messages)
+          .await
+          .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+      )
+    },
+    false => None,
+  };
+
+  let silences = match include("silences") {
+    true => Some(
+      chat::silences(&state.pool, query.since, query.history_since)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    ),
+    false => None,
+  };
+
+  Ok(Json(Updates {
+    messages,
+    presence,
+    silences,
+  }))
+}
+
+/// Sent periodically by clients reading chat over the websocket, answered with the silences they missed
+async fn ack(
+  State(state): State<FiberState>,
+  Query(query): Query<AckParams>,
+  body: Result<TypedMultipart<AckParams>, TypedMultipartError>,
+) -> Result<Json<ChatAck>, StatusCode> {
+  let body = body.map(|TypedMultipart(body)| body).unwrap_or_default();
+
+  let silences = chat::silences(&state.pool, body.since.or(query.since), body.history_since.or(query.history_since))
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+  Ok(Json(ChatAck { silences }))
+}
+
+/// Responds with 403 unless the user is a moderator
+async fn require_moderator(state: &FiberState, user: &User) -> Result<(), Response> {
+  let is_moderator = chat::is_moderator(&state.pool, user.id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  if !is_moderator {
+    return Err(error(StatusCode::FORBIDDEN, "only moderators can do that"));
+  }
+
+  Ok(())
+}
+
+/// Silences a user for a while, they can't send messages and their messages are hidden by clients
+async fn silence_user(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Path(target_id): Path<i64>,
+  Json(body): Json<SilenceRequest>,
+) -> Result<Json<serde_json::Value>, Response> {
+  require_moderator(&state, &user).await?;
+
+  if !(1..=MAX_SILENCE_DURATION).contains(&body.duration) {
+    return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "invalid duration"));
+  }
+
+  let target_is_moderator = chat::is_moderator(&state.pool, target_id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  if target_id == user.id || target_is_moderator {
+    return Err(error(StatusCode::FORBIDDEN, "moderators can't be silenced"));
+  }
+
+  let exists = sqlx::query_scalar::<_, bool>("select exists (select 1 from users where id = ?)")
+    .bind(target_id)
+    .fetch_one(&state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  if !exists {
+    return Err(error(StatusCode::NOT_FOUND, "user not found"));
+  }
+
+  let silence = chat::silence(&state.pool, target_id, Some(user.id), TimeDelta::seconds(body.duration), body.reason.trim())
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  state.notifications.end_chat(target_id);
+
+  println!("[chat] {} silenced user {} until {}", user.username, target_id, silence.ends_at);
+
+  Ok(Json(json!({
+    "id": silence.id,
+    "user_id": silence.user_id,
+    "reason": silence.reason,
+    "ends_at": silence.ends_at.to_rfc3339(),
+  })))
+}
+
+async fn unsilence_user(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Path(target_id): Path<i64>,
+) -> Result<StatusCode, Response> {
+  require_moderator(&state, &user).await?;
+
+  let silenced = chat::unsilence(&state.pool, target_id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  if !silenced {
+    return Err(error(StatusCode::NOT_FOUND, "user isn't silenced"));
+  }
+
+  Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_message(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Path(message_id): Path<i64>,
+) -> Result<StatusCode, Response> {
+  require_moderator(&state, &user).await?;
+
+  let message = chat::delete_message(&state.pool, message_id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+    .ok_or_else(|| error(StatusCode::NOT_FOUND, "message not found"))?;
+
+  println!("[chat] {} deleted message {} by user {}", user.username, message.id, message.sender_id);
+
+  Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn router(state: FiberState) -> Router<FiberState> {
+  let read = Router::new()
+    .route("/api/v2/chat/channels", get(channels))
+    .route("/api/v2/chat/channels/{channel}", get(get_channel))
+    .route("/api/v2/chat/channels/{channel}/messages", get(get_messages))
+    .route("/api/v2/chat/updates", get(updates))
+    .route("/api/v2/chat/ack", post(ack))
+    .layer(middleware::from_fn_with_state((state.clone(), "chat.read"), auth::middleware));
+
+  Router::new()
+    .route("/api/v2/chat/channels/{channel}/users/{user}", put(join_channel).delete(leave_channel))
+    .route("/api/v2/chat/channels/{channel}/messages", post(send_message))
+    .route("/api/v2/chat/channels/{channel}/mark-as-read/{message}", put(mark_as_read))
+    .route("/api/v2/chat/new", post(new_pm))
+    .route("/api/v2/chat/users/{id}/silence", post(silence_user).delete(unsilence_user))
+    .route("/api/v2/chat/messages/{id}", delete(delete_message))
+    .layer(middleware::from_fn_with_state((state, "chat.write"), auth::middleware))
+    .merge(read)
+}
\ No newline at end of file
@@ -0,0 +1,134 @@
+use axum::{body::Bytes, extract::{DefaultBodyLimit, Path, State}, http::{header, StatusCode}, middleware, response::{IntoResponse, Response}, routing::{get, post}, Extension, Json, Router};
+use axum_typed_multipart::{FieldData, TryFromMultipart, TypedMultipart};
+use serde_json::{json, Value};
+
+use crate::{auth::{self, User}, images, routes::error, state::FiberState};
+
+const DEFAULT_AVATAR: &[u8] = include_bytes!("../../assets/avatar.png");
+const DEFAULT_COVER: &[u8] = include_bytes!("../../assets/cover.jpg");
+
+/// largest image accepted before it's resized
+const MAX_IMAGE_SIZE: usize = 8 * 1024 * 1024;
+
+#[derive(TryFromMultipart)]
+struct ImageMultipart {
+  #[form_data(limit = "8MiB")]
+  file: FieldData<Bytes>,
+}
+
+#[derive(Clone, Copy)]
+enum ProfileImage {
+  Avatar,
+  Cover,
+}
+
+impl ProfileImage {
+  fn column(self) -> &'static str {
+    match self {
+      Self::Avatar => "avatar_hash",
+      Self::Cover => "cover_hash",
+    }
+  }
+
+  /// Field the new link is returned in
+  fn field(self) -> &'static str {
+    match self {
+      Self::Avatar => "avatar_url",
+      Self::Cover => "cover_url",
+    }
+  }
+
+  fn process(self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    match self {
+      Self::Avatar => images::avatar(data),
+      Self::Cover => images::cover(data),
+    }
+  }
+}
+
+/// Resizes and stores an uploaded image, then points the user's profile at it
+async fn upload(state: &FiberState, user: &User, kind: ProfileImage, data: Bytes) -> Result<Json<Value>, Response> {
+  let image = tokio::task::spawn_blocking(move || kind.process(&data))
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+    .map_err(|e| error(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()))?;
+
+  let hash = state.images.put(&image)
+    .await
+    .map_err(|e| {
+      eprintln!("failed to store image: {:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })?;
+
+  sqlx::query(&format!("update users set {} = ? where id = ?", kind.column()))
+    .bind(&hash)
+    .bind(user.id)
+    .execute(&state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  Ok(Json(json!({ kind.field(): state.config.image_url(&hash) })))
+}
+
+async fn upload_avatar(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  TypedMultipart(body): TypedMultipart<ImageMultipart>,
+) -> Result<Json<Value>, Response> {
+  upload(&state, &user, ProfileImage::Avatar, body.file.contents).await
+}
+
+async fn upload_cover(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  TypedMultipart(body): TypedMultipart<ImageMultipart>,
+) -> Result<Json<Value>, Response> {
+  upload(&state, &user, ProfileImage::Cover, body.file.contents).await
+}
+
+/// Avatar and cover of users that haven't uploaded their own
+async fn default_avatar() -> Response {
+  ([(header::CONTENT_TYPE, "image/png")], DEFAULT_AVATAR).into_response()
+}
+
+async fn default_cover() -> Response {
+  ([(header::CONTENT_TYPE, "image/jpeg")], DEFAULT_COVER).into_response()
+}
+
+/// Serves a stored image, they're addressed by their hash so they never change
+async fn get_image(
+  State(state): State<FiberState>,
+  Path(hash): Path<String>,
+) -> Result<Response, StatusCode> {
+  if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase()) {
+    return Err(StatusCode::NOT_FOUND);
+  }
+
+  let data = state.images.get(&hash)
+    .await
+    .map_err(|_| StatusCode::NOT_FOUND)?;
+
+  let content_type = match image::guess_format(&data) {
+    Ok(format) => format.to_mime_type(),
+    Err(_) => "application/octet-stream",
+  };
+
+  Ok((
+    [
+      (header::CONTENT_TYPE, content_type),
+      (header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+    ],
+    data,
+  ).into_response())
+}
+
+pub fn router(state: FiberState) -> Router<FiberState> {
+  Router::new()
+    .route("/api/v2/me/avatar", post(upload_avatar))
+    .route("/api/v2/me/cover", post(upload_cover))
+    .layer(DefaultBodyLimit::max(MAX_IMAGE_SIZE))
+    .layer(middleware::from_fn_with_state((state, "*"), auth::middleware))
+    .route("/images/{hash}", get(get_image))
+    .route(&format!("/images/{}", images::DEFAULT_AVATAR), get(default_avatar))
+    .route(&format!("/images/{}", images::DEFAULT_COVER), get(default_cover))
+}
\ No newline at end of file
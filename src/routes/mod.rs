@@ -1,3 +1,113 @@
+use axum::{body::{Body, Bytes}, http::{header, HeaderMap, HeaderValue, StatusCode}, response::{IntoResponse, Response}, Json};
+use serde_json::json;
+
+pub mod admin;
+pub mod beatmaps;
+pub mod chat;
+pub mod images;
+pub mod notifications;
 pub mod oauth;
+pub mod relations;
+pub mod scores;
+pub mod search;
+pub mod session;
 pub mod signalr;
-pub mod users;
\ No newline at end of file
+pub mod users;
+
+/// Error response in the `{ "error": message }` shape the client shows to the user
+pub fn error(status: StatusCode, message: &str) -> Response {
+  (status, Json(json!({ "error": message }))).into_response()
+}
+
+/// Collects every value of a repeated query parameter, `key[]=a&key[]=b` or `key=a&key=b`
+pub fn query_list(query: &str, key: &str) -> Vec<String> {
+  let array_key = format!("{}[]", key);
+
+  form_urlencoded::parse(query.as_bytes())
+    .filter(|(k, _)| k == key || *k == array_key)
+    .map(|(_, value)| value.into_owned())
+    .collect()
+}
+
+/// Parses a single `bytes=` range into an inclusive start and end.
+///
+/// `None` means the whole file should be sent, `Some(Err(()))` that the range can't be satisfied.
+fn parse_range(range: &str, len: usize) -> Option<Result<(usize, usize), ()>> {
+  let range = range.strip_prefix("bytes=")?;
+
+  // multiple ranges aren't worth supporting, servers are allowed to send everything instead
+  if range.contains(',') {
+    return None;
+  }
+
+  let (start, end) = range.trim().split_once('-')?;
+
+  let (start, end) = match (start.parse::<usize>().ok(), end.parse::<usize>().ok()) {
+    (Some(start), Some(end)) => (start, end.min(len.saturating_sub(1))),
+    (Some(start), None) if end.is_empty() => (start, len.saturating_sub(1)),
+    (None, Some(suffix)) if start.is_empty() => (len.saturating_sub(suffix), len.saturating_sub(1)),
+    _ => return None,
+  };
+
+  if start >= len || start > end {
+    return Some(Err(()));
+  }
+
+  Some(Ok((start, end)))
+}
+
+/// Percent-encodes a filename for `filename*`
+fn encode_filename(filename: &str) -> String {
+  filename.bytes()
+    .map(|byte| match byte {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => (byte as char).to_string(),
+      byte => format!("%{:02X}", byte),
+    })
+    .collect()
+}
+
+/// Sends `data` as a download named `filename`, answering range requests with only the requested part
+pub fn attachment(headers: &HeaderMap, data: Vec<u8>, content_type: &str, filename: &str) -> Response {
+  let len = data.len();
+
+  // the plain filename has to stay ascii, clients that understand `filename*` get the real one
+  let ascii_filename = filename.chars()
+    .map(|c| match c {
+      '"' | '\\' | '/' | ':' | '*' | '?' | '<' | '>' | '|' => '_',
+      c if c.is_ascii() && !c.is_ascii_control() => c,
+      _ => '_',
+    })
+    .collect::<String>();
+
+  let disposition = format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", ascii_filename, encode_filename(filename));
+
+  let mut response_headers = HeaderMap::new();
+  response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+  response_headers.insert(header::CONTENT_TYPE, HeaderValue::from_str(content_type).unwrap_or(HeaderValue::from_static("application/octet-stream")));
+
+  if let Ok(disposition) = HeaderValue::from_str(&disposition) {
+    response_headers.insert(header::CONTENT_DISPOSITION, disposition);
+  }
+
+  let range = headers.get(header::RANGE)
+    .and_then(|range| range.to_str().ok())
+    .and_then(|range| parse_range(range, len));
+
+  match range {
+    None => (StatusCode::OK, response_headers, data).into_response(),
+    Some(Ok((start, end))) => {
+      if let Ok(content_range) = HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, len)) {
+        response_headers.insert(header::CONTENT_RANGE, content_range);
+      }
+
+      (StatusCode::PARTIAL_CONTENT, response_headers, Body::from(Bytes::from(data).slice(start..=end))).into_response()
+    },
+    Some(Err(())) => {
+      if let Ok(content_range) = HeaderValue::from_str(&format!("bytes */{}", len)) {
+        response_headers.insert(header::CONTENT_RANGE, content_range);
+      }
+
+      (StatusCode::RANGE_NOT_SATISFIABLE, response_headers).into_response()
+    },
+  }
+}
\ No newline at end of file
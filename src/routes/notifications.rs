@@ -0,0 +1,210 @@
+use axum::{body::Body, extract::{ws::{Message, WebSocket}, Query, State, WebSocketUpgrade}, http::StatusCode, middleware, response::Response, routing::{get, post}, Extension, Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{auth::{self, User}, chat, notifications::{ApiNotification, DbNotification}, state::FiberState};
+
+/// most notifications returned at once
+const NOTIFICATION_LIMIT: i64 = 50;
+
+/// Event sent by the client, only the ones for starting and ending chat exist
+#[derive(Deserialize)]
+struct ClientEvent {
+  event: String,
+}
+
+async fn notifications_upgrade(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  ws: WebSocketUpgrade,
+) -> Response<Body> {
+  ws.on_upgrade(move |ws| notifications_ws(state, user, ws))
+}
+
+async fn notifications_ws(state: FiberState, user: User, mut ws: WebSocket) {
+  let (id, mut events) = state.notifications.register(user.id);
+  state.online.connect(user.id);
+
+  println!("[notifications] New connection (user {})", user.id);
+
+  loop {
+    tokio::select! {
+      msg = ws.recv() => {
+        let Some(Ok(msg)) = msg else {
+          break
+        };
+
+        match msg {
+          Message::Text(text) => {
+            let Ok(event) = serde_json::from_str::<ClientEvent>(&text) else {
+              continue
+            };
+
+            match event.event.as_str() {
+              // silenced users don't get live chat until their silence is over
+              "chat.start" => match chat::is_silenced(&state.pool, user.id).await {
+                Ok(false) => state.notifications.set_chat(user.id, id, true),
+                Ok(true) => {
+                  let error = json!({ "event": "chat.start", "error": "You can't chat while you're silenced." });
+
+                  if ws.send(Message::Text(error.to_string().into())).await.is_err() {
+                    break
+                  }
+                },
+                Err(e) => eprintln!("failed to look up silences: {:?}", e),
+              },
+              "chat.end" => state.notifications.set_chat(user.id, id, false),
+              event => println!("[notifications] Unknown event {}", event),
+            }
+          },
+          Message::Close(_) => break,
+          _ => {},
+        }
+      },
+      Some(event) = events.recv() => {
+        if ws.send(Message::Text(event.into())).await.is_err() {
+          break
+        }
+      },
+    }
+  }
+
+  state.notifications.unregister(user.id, id);
+  state.online.disconnect(user.id);
+}
+
+#[derive(Deserialize)]
+struct NotificationsQuery {
+  /// only notifications up to this id, for fetching older ones
+  max_id: Option<i64>,
+  #[serde(rename = "cursor[id]")]
+  cursor_id: Option<i64>,
+  #[serde(default)]
+  unread_only: bool,
+}
+
+#[derive(Serialize)]
+struct Cursor {
+  id: i64,
+}
+
+#[derive(Serialize)]
+struct Notifications {
+  has_more: bool,
+  notifications: Vec<ApiNotification>,
+  unread_count: i64,
+  notification_endpoint: String,
+  cursor: Option<Cursor>,
+}
+
+async fn notifications(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Query(query): Query<NotificationsQuery>,
+) -> Result<Json<Notifications>, StatusCode> {
+  // the cursor points at the last notification of the previous page, max_id includes itself
+  let max_id = query.cursor_id.map(|id| id - 1).or(query.max_id);
+
+  let mut notifications = sqlx::query_as::<_, DbNotification>(r#"
+    select * from notifications
+    where user_id = ?1 and (?2 is null or id <= ?2) and (not ?3 or not is_read)
+    order by id desc
+    limit ?4
+  "#)
+    .bind(user.id)
+    .bind(max_id)
+    .bind(query.unread_only)
+    .bind(NOTIFICATION_LIMIT + 1)
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+  let has_more = notifications.len() as i64 > NOTIFICATION_LIMIT;
+  notifications.truncate(NOTIFICATION_LIMIT as usize);
+
+  let unread_count = sqlx::query_scalar::<_, i64>("select count(*) from notifications where user_id = ? and not is_read")
+    .bind(user.id)
+    .fetch_one(&state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+  Ok(Json(Notifications {
+    has_more,
+    cursor: has_more.then(|| notifications.last().map(|notification| Cursor { id: notification.id })).flatten(),
+    notifications: notifications.iter().map(ApiNotification::new).collect(),
+    unread_count,
+    notification_endpoint: state.config.notification_endpoint.clone(),
+  }))
+}
+
+#[derive(Deserialize)]
+struct NotificationId {
+  id: i64,
+}
+
+/// Every notification about an object, or of a type when there's no `object_id`
+#[derive(Deserialize)]
+struct NotificationIdentity {
+  object_type: String,
+  object_id: Option<i64>,
+}
+
+#[derive(Default, Deserialize)]
+struct MarkRead {
+  #[serde(default)]
+  notifications: Vec<NotificationId>,
+  #[serde(default)]
+  identities: Vec<NotificationIdentity>,
+}
+
+/// Marks notifications as read, telling the user's other clients about it
+async fn mark_read(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  body: Option<Json<MarkRead>>,
+) -> Result<StatusCode, StatusCode> {
+  let Json(body) = body.unwrap_or_default();
+
+  let ids = body.notifications.iter().map(|notification| notification.id).collect::<Vec<_>>();
+
+  let identities = body.identities.iter()
+    .map(|identity| json!({ "object_type": identity.object_type, "object_id": identity.object_id }))
+    .collect::<Vec<_>>();
+
+  let read = sqlx::query_scalar::<_, i64>(r#"
+    update notifications
+    set is_read = true
+    where user_id = ?1 and not is_read and (
+      id in (select value from json_each(?2))
+      or exists (
+        select 1 from json_each(?3) i
+        where json_extract(i.value, '$.object_type') = object_type
+          and coalesce(json_extract(i.value, '$.object_id'), object_id) = object_id
+      )
+    )
+    returning id
+  "#)
+    .bind(user.id)
+    .bind(json!(ids).to_string())
+    .bind(json!(identities).to_string())
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+  if !read.is_empty() {
+    state.notifications.send(&[user.id], "read", &json!({
+      "notifications": read.iter().map(|id| json!({ "id": id })).collect::<Vec<_>>(),
+      "read_count": read.len(),
+    }));
+  }
+
+  Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn router(state: FiberState) -> Router<FiberState> {
+  Router::new()
+    .route("/notifications", get(notifications_upgrade))
+    .route("/api/v2/notifications", get(notifications))
+    .route("/api/v2/notifications/mark-read", post(mark_read))
+    .layer(middleware::from_fn_with_state((state, "*"), auth::middleware))
+}
\ No newline at end of file
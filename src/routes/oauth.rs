@@ -1,67 +1,302 @@
-use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+use axum::{extract::State, http::StatusCode, middleware, response::{IntoResponse, Response}, routing::{delete, post}, Extension, Json, Router};
 use axum_typed_multipart::{TryFromMultipart, TypedMultipart};
+use chrono::Utc;
 use serde::Serialize;
 
-use crate::{auth::User, state::FiberState};
+use crate::{auth::{self, hash_password, issue_token, revoke_token, verify_password, OAuthClient, Token, User}, routes::{session::send_verification_code, users::{validate_password, validate_username}}, state::FiberState};
 
 #[derive(TryFromMultipart)]
 struct TokenMultipart {
-  username: String,
-  #[allow(unused)]
-  password: String,
+  grant_type: Option<String>,
+  client_id: Option<i64>,
+  client_secret: Option<String>,
+  username: Option<String>,
+  password: Option<String>,
+  refresh_token: Option<String>,
+  scope: Option<String>,
 }
 
 #[derive(Serialize)]
 struct TokenResponse {
   access_token: String,
-  refresh_token: String,
-  #[serde(rename = "type")]
-  ty: String,
-  expires_in: u32,
+  refresh_token: Option<String>,
+  token_type: String,
+  expires_in: i64,
 }
 
-async fn token(
-  State(state): State<FiberState>,
-  body: TypedMultipart<TokenMultipart>,
-) -> Result<Json<TokenResponse>, StatusCode> {
-  match sqlx::query_as::<_, User>(r#"
-    select * from users
+impl From<Token> for TokenResponse {
+  fn from(token: Token) -> Self {
+    Self {
+      expires_in: token.expires_in(),
+      access_token: token.access_token,
+      refresh_token: token.refresh_token,
+      token_type: "Bearer".into(),
+    }
+  }
+}
+
+/// Error body in the shape the client expects from the token endpoint
+#[derive(Serialize)]
+pub struct OAuthError {
+  #[serde(skip)]
+  status: StatusCode,
+  error: String,
+  error_description: String,
+  hint: Option<String>,
+  message: String,
+}
+
+impl OAuthError {
+  pub fn new(status: StatusCode, error: &str, description: &str) -> Self {
+    Self {
+      status,
+      error: error.into(),
+      error_description: description.into(),
+      hint: None,
+      message: description.into(),
+    }
+  }
+
+  pub fn with_hint(mut self, hint: &str) -> Self {
+    self.hint = Some(hint.into());
+
+    self
+  }
+
+  fn invalid_request(hint: &str) -> Self {
+    Self::new(StatusCode::BAD_REQUEST, "invalid_request", "The request is missing a required parameter.")
+      .with_hint(hint)
+  }
+
+  fn invalid_client() -> Self {
+    Self::new(StatusCode::UNAUTHORIZED, "invalid_client", "Client authentication failed")
+  }
+
+  fn invalid_scope(scope: &str) -> Self {
+    Self::new(StatusCode::BAD_REQUEST, "invalid_scope", "The requested scope is invalid, unknown, or malformed")
+      .with_hint(&format!("Check the `{}` scope", scope))
+  }
+
+  fn server_error() -> Self {
+    Self::new(StatusCode::INTERNAL_SERVER_ERROR, "server_error", "The server encountered an internal error.")
+  }
+}
+
+impl IntoResponse for OAuthError {
+  fn into_response(self) -> Response {
+    (self.status, Json(self)).into_response()
+  }
+}
+
+async fn authenticate_client(
+  state: &FiberState,
+  body: &TokenMultipart,
+) -> Result<OAuthClient, OAuthError> {
+  let (Some(id), Some(secret)) = (body.client_id, &body.client_secret) else {
+    return Err(OAuthError::invalid_client());
+  };
+
+  sqlx::query_as::<_, OAuthClient>(r#"
+    select * from oauth_clients
+    where id = ?
+  "#)
+    .bind(id)
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(|_| OAuthError::server_error())?
+    .filter(|client| client.verify_secret(secret))
+    .ok_or_else(OAuthError::invalid_client)
+}
+
+/// Checks every requested scope against what the client is allowed to use
+fn validate_scopes<'a>(client: &OAuthClient, requested: &'a str) -> Result<&'a str, OAuthError> {
+  if let Some(scope) = requested.split(' ').find(|scope| !client.allows_scope(scope)) {
+    return Err(OAuthError::invalid_scope(scope));
+  }
+
+  Ok(requested)
+}
+
+async fn password_grant(
+  state: &FiberState,
+  client: &OAuthClient,
+  body: &TokenMultipart,
+) -> Result<Token, OAuthError> {
+  let Some(username) = &body.username else {
+    return Err(OAuthError::invalid_request("Check the `username` parameter"));
+  };
+
+  let Some(password) = &body.password else {
+    return Err(OAuthError::invalid_request("Check the `password` parameter"));
+  };
+
+  let invalid = || OAuthError::new(StatusCode::BAD_REQUEST, "invalid_grant", "The user credentials were incorrect.")
+    .with_hint("Incorrect sign in");
+
+  let user_id = match sqlx::query_as::<_, (i64, String)>(r#"
+    select id, password_hash from users
     where username = ?
   "#)
-    .bind(&body.username)
-    .fetch_one(&state.pool)
+    .bind(username)
+    .fetch_optional(&state.pool)
     .await
+    .map_err(|_| OAuthError::server_error())?
   {
-    Ok(user) => Ok(Json(TokenResponse {
-      access_token: user.id.to_string(),
-      refresh_token: user.id.to_string(),
-      ty: "Bearer".into(),
-      expires_in: u32::MAX,
-    })),
-    Err(e) => {
-      println!("{:?}", e);
-      match sqlx::query_scalar::<_, i64>(r#"
-        insert into users (username, joined_at) values
-        (?, date())
+    Some((id, hash)) => {
+      if !verify_password(password, &hash) {
+        return Err(invalid());
+      }
+
+      id
+    },
+    None if state.config.auto_register => {
+      if !validate_username(username).is_empty() || !validate_password(username, password).is_empty() {
+        return Err(invalid());
+      }
+
+      let hash = hash_password(password)
+        .map_err(|_| OAuthError::server_error())?;
+
+      sqlx::query_scalar::<_, i64>(r#"
+        insert into users (username, password_hash, joined_at) values
+        (?, ?, ?)
         returning id
       "#)
-        .bind(&body.username)
+        .bind(username)
+        .bind(hash)
+        .bind(Utc::now())
         .fetch_one(&state.pool)
         .await
-      {
-        Ok(id) => Ok(Json(TokenResponse {
-          access_token: id.to_string(),
-          refresh_token: id.to_string(),
-          ty: "Bearer".into(),
-          expires_in: u32::MAX,
-        })),
-        _ => Err(StatusCode::INTERNAL_SERVER_ERROR)
-      }
+        .map_err(|_| OAuthError::server_error())?
+    },
+    None => return Err(invalid()),
+  };
+
+  let scopes = validate_scopes(client, body.scope.as_deref().unwrap_or("*"))?;
+
+  let verified = !state.config.session_verification;
+
+  let token = issue_token(&state.pool, client.id, Some(user_id), scopes, verified)
+    .await
+    .map_err(|_| OAuthError::server_error())?;
+
+  if !verified {
+    let user = sqlx::query_as::<_, User>(r#"
+      select * from users
+      where id = ?
+    "#)
+      .bind(user_id)
+      .fetch_one(&state.pool)
+      .await
+      .map_err(|_| OAuthError::server_error())?;
+
+    if let Err(e) = send_verification_code(state, token.id, &user).await {
+      eprintln!("{:?}", e);
     }
   }
+
+  Ok(token)
+}
+
+async fn refresh_token_grant(
+  state: &FiberState,
+  client: &OAuthClient,
+  body: &TokenMultipart,
+) -> Result<Token, OAuthError> {
+  let Some(refresh_token) = &body.refresh_token else {
+    return Err(OAuthError::invalid_request("Check the `refresh_token` parameter"));
+  };
+
+  let invalid = || OAuthError::new(StatusCode::UNAUTHORIZED, "invalid_request", "The refresh token is invalid.")
+    .with_hint("Token has been revoked");
+
+  let mut tx = state.pool.begin()
+    .await
+    .map_err(|_| OAuthError::server_error())?;
+
+  // refresh tokens are single use, revoking the old pair claims it so concurrent refreshes can't both rotate it
+  let old = sqlx::query_as::<_, Token>(r#"
+    update oauth_tokens
+    set revoked = true
+    where refresh_token = ? and client_id = ? and not revoked and refresh_expires_at > ?
+    returning *
+  "#)
+    .bind(refresh_token)
+    .bind(client.id)
+    .bind(Utc::now())
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|_| OAuthError::server_error())?
+    .ok_or_else(invalid)?;
+
+  // a refreshed session stays verified
+  let token = issue_token(&mut *tx, client.id, old.user_id, &old.scopes, old.verified)
+    .await
+    .map_err(|_| OAuthError::server_error())?;
+
+  tx.commit()
+    .await
+    .map_err(|_| OAuthError::server_error())?;
+
+  Ok(token)
+}
+
+/// Issues a token without a user, for tools acting on their own behalf
+async fn client_credentials_grant(
+  state: &FiberState,
+  client: &OAuthClient,
+  body: &TokenMultipart,
+) -> Result<Token, OAuthError> {
+  let scopes = validate_scopes(client, body.scope.as_deref().unwrap_or("public"))?;
+
+  // "*" is only meant for tokens acting on behalf of a user
+  if scopes.split(' ').any(|scope| scope == "*") {
+    return Err(OAuthError::invalid_scope("*"));
+  }
+
+  issue_token(&state.pool, client.id, None, scopes, true)
+    .await
+    .map_err(|_| OAuthError::server_error())
+}
+
+async fn token(
+  State(state): State<FiberState>,
+  body: TypedMultipart<TokenMultipart>,
+) -> Result<Json<TokenResponse>, OAuthError> {
+  let client = authenticate_client(&state, &body).await?;
+
+  let token = match body.grant_type.as_deref() {
+    Some("password") => password_grant(&state, &client, &body).await?,
+    Some("refresh_token") => refresh_token_grant(&state, &client, &body).await?,
+    Some("client_credentials") => client_credentials_grant(&state, &client, &body).await?,
+    Some(_) => return Err(OAuthError::new(
+      StatusCode::BAD_REQUEST,
+      "unsupported_grant_type",
+      "The authorization grant type is not supported by the authorization server.",
+    )),
+    None => return Err(OAuthError::invalid_request("Check the `grant_type` parameter")),
+  };
+
+  Ok(Json(token.into()))
+}
+
+async fn revoke_current(
+  State(state): State<FiberState>,
+  Extension(token): Extension<Token>,
+) -> StatusCode {
+  match revoke_token(&state.pool, token.id).await {
+    Ok(_) => StatusCode::NO_CONTENT,
+    Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+  }
 }
 
 pub fn router() -> Router<FiberState> {
   Router::new()
     .route("/token", post(token))
+}
+
+pub fn tokens_router(state: FiberState) -> Router<FiberState> {
+  Router::new()
+    .route("/api/v2/oauth/tokens/current", delete(revoke_current))
+    .layer(middleware::from_fn_with_state((state, "identify"), auth::unverified_middleware))
 }
\ No newline at end of file
@@ -0,0 +1,241 @@
+use axum::{extract::{Path, Query, State}, http::StatusCode, middleware, response::{IntoResponse, Response}, routing::{delete, get, post}, Extension, Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::prelude::FromRow;
+
+use crate::{auth::{self, User}, notifications::{self, NewNotification}, relations::{self, RelationType, Restriction}, routes::{error, users::ApiUser}, state::FiberState};
+
+/// most friends a user can have, supporters get twice as many
+const MAX_FRIENDS: i64 = 250;
+
+const MAX_BLOCKS: i64 = 100;
+
+#[derive(FromRow)]
+struct DbRelation {
+  relation_type: i64,
+  mutual: bool,
+  /// false when the target blocked the user
+  presence_visible: bool,
+  #[sqlx(flatten)]
+  target: User,
+}
+
+#[derive(Serialize)]
+struct ApiRelation {
+  target_id: i64,
+  relation_type: RelationType,
+  mutual: bool,
+  target: ApiUser,
+}
+
+impl ApiRelation {
+  fn new(relation: &DbRelation, state: &FiberState) -> Option<Self> {
+    Some(Self {
+      target_id: relation.target.id,
+      relation_type: RelationType::from_id(relation.relation_type)?,
+      mutual: relation.mutual,
+      target: ApiUser::new(&relation.target, &state.config).with_presence(state, relation.presence_visible),
+    })
+  }
+}
+
+#[derive(Deserialize)]
+struct RelationQuery {
+  target: i64,
+}
+
+async fn fetch_relations(state: &FiberState, user_id: i64, relation_type: RelationType, target_id: Option<i64>) -> sqlx::Result<Vec<DbRelation>> {
+  sqlx::query_as::<_, DbRelation>(r#"
+    select
+      r.relation_type,
+      r.relation_type = 0 and exists (
+        select 1 from relations m
+        where m.user_id = r.target_id and m.target_id = r.user_id and m.relation_type = 0
+      ) as mutual,
+      not exists (
+        select 1 from relations b
+        where b.user_id = r.target_id and b.target_id = r.user_id and b.relation_type = 1
+      ) as presence_visible,
+      u.*
+    from relations r
+    join users u on u.id = r.target_id
+    where r.user_id = ?1 and r.relation_type = ?2 and (?3 is null or r.target_id = ?3)
+    order by u.username
+  "#)
+    .bind(user_id)
+    .bind(relation_type.id())
+    .bind(target_id)
+    .fetch_all(&state.pool)
+    .await
+}
+
+async fn list(state: &FiberState, user: &User, relation_type: RelationType) -> Result<Json<Vec<ApiRelation>>, StatusCode> {
+  let relations = fetch_relations(state, user.id, relation_type, None)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+  Ok(Json(relations.iter().filter_map(|relation| ApiRelation::new(relation, state)).collect()))
+}
+
+/// Adds or replaces the user's relation with `target_id`, a user can't be friended and blocked at the same time
+async fn add(state: &FiberState, user: &User, relation_type: RelationType, target_id: i64) -> Result<Json<serde_json::Value>, Response> {
+  if target_id == user.id {
+    return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "you can't add yourself"));
+  }
+
+  let exists = sqlx::query_scalar::<_, bool>("select exists (select 1 from users where id = ?)")
+    .bind(target_id)
+    .fetch_one(&state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  if !exists {
+    return Err(error(StatusCode::NOT_FOUND, "user not found"));
+  }
+
+  let current = relations::relation(&state.pool, user.id, target_id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  if current != Some(relation_type) {
+    if relation_type == RelationType::Friend
+      && relations::relation(&state.pool, target_id, user.id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+        == Some(RelationType::Block)
+    {
+      return Err(error(StatusCode::FORBIDDEN, Restriction::Blocked.message()));
+    }
+
+    let limit = match relation_type {
+      RelationType::Friend if user.is_supporter => MAX_FRIENDS * 2,
+      RelationType::Friend => MAX_FRIENDS,
+      RelationType::Block => MAX_BLOCKS,
+    };
+
+    let count = sqlx::query_scalar::<_, i64>("select count(*) from relations where user_id = ? and relation_type = ?")
+      .bind(user.id)
+      .bind(relation_type.id())
+      .fetch_one(&state.pool)
+      .await
+      .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+    if count >= limit {
+      return Err(error(StatusCode::UNPROCESSABLE_ENTITY, match relation_type {
+        RelationType::Friend => "too many friends",
+        RelationType::Block => "too many blocked users",
+      }));
+    }
+
+    sqlx::query(r#"
+      insert into relations (user_id, target_id, relation_type)
+      values (?, ?, ?)
+      on conflict (user_id, target_id) do update set relation_type = excluded.relation_type, created_at = current_timestamp
+    "#)
+      .bind(user.id)
+      .bind(target_id)
+      .bind(relation_type.id())
+      .execute(&state.pool)
+      .await
+      .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+    if relation_type == RelationType::Friend {
+      let notification = NewNotification {
+        name: "user_friend_new",
+        object_type: "user",
+        object_id: user.id,
+        source_user_id: Some(user.id),
+        details: json!({ "username": user.username, "cover_url": user.avatar_url(&state.config) }),
+      };
+
+      if let Err(e) = notifications::notify(state, target_id, notification).await {
+        eprintln!("failed to send notification: {:?}", e);
+      }
+    }
+  }
+
+  let relation = fetch_relations(state, user.id, relation_type, Some(target_id))
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  let relation = relation.first()
+    .and_then(|relation| ApiRelation::new(relation, state))
+    .ok_or_else(|| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  Ok(Json(json!({ "user_relation": relation })))
+}
+
+async fn remove(state: &FiberState, user: &User, relation_type: RelationType, target_id: i64) -> Result<StatusCode, StatusCode> {
+  sqlx::query(r#"
+    delete from relations
+    where user_id = ? and target_id = ? and relation_type = ?
+  "#)
+    .bind(user.id)
+    .bind(target_id)
+    .bind(relation_type.id())
+    .execute(&state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+  Ok(StatusCode::NO_CONTENT)
+}
+
+async fn friends(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+) -> Result<Json<Vec<ApiRelation>>, StatusCode> {
+  list(&state, &user, RelationType::Friend).await
+}
+
+async fn add_friend(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Query(query): Query<RelationQuery>,
+) -> Result<Json<serde_json::Value>, Response> {
+  add(&state, &user, RelationType::Friend, query.target).await
+}
+
+async fn remove_friend(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Path(target_id): Path<i64>,
+) -> Result<StatusCode, StatusCode> {
+  remove(&state, &user, RelationType::Friend, target_id).await
+}
+
+async fn blocks(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+) -> Result<Json<Vec<ApiRelation>>, StatusCode> {
+  list(&state, &user, RelationType::Block).await
+}
+
+async fn add_block(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Query(query): Query<RelationQuery>,
+) -> Result<Json<serde_json::Value>, Response> {
+  add(&state, &user, RelationType::Block, query.target).await
+}
+
+async fn remove_block(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Path(target_id): Path<i64>,
+) -> Result<StatusCode, StatusCode> {
+  remove(&state, &user, RelationType::Block, target_id).await
+}
+
+pub fn router(state: FiberState) -> Router<FiberState> {
+  let read = Router::new()
+    .route("/api/v2/friends", get(friends))
+    .layer(middleware::from_fn_with_state((state.clone(), "friends.read"), auth::middleware));
+
+  Router::new()
+    .route("/api/v2/friends", post(add_friend))
+    .route("/api/v2/friends/{id}", delete(remove_friend))
+    .route("/api/v2/blocks", get(blocks).post(add_block))
+    .route("/api/v2/blocks/{id}", delete(remove_block))
+    .layer(middleware::from_fn_with_state((state, "*"), auth::middleware))
+    .merge(read)
+}
\ No newline at end of file
@@ -0,0 +1,955 @@
+use std::collections::HashMap;
+
+use axum::{body::Bytes, extract::{DefaultBodyLimit, Path, Query, RawQuery, State}, http::{HeaderMap, StatusCode}, middleware, response::{IntoResponse, Response}, routing::{get, post, put}, Extension, Json, Router};
+use axum_typed_multipart::{TryFromMultipart, TypedMultipart};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{prelude::FromRow, QueryBuilder, Sqlite};
+
+use crate::{auth::{self, User}, beatmaps::{difficulty, status::BeatmapStatus}, config::Config, routes::{attachment, beatmaps::{fetch_beatmap, fetch_beatmaps, fetch_beatmapsets, ApiBeatmap, ApiBeatmapset, DbBeatmap, DbBeatmapset}, error, query_list, users::ApiUser}, ruleset::Ruleset, scores::{processing::{self, BeatmapLimits}, replay::ReplayHeader, statistics}, state::FiberState};
+
+const RANKS: [&str; 9] = ["XH", "X", "SH", "S", "A", "B", "C", "D", "F"];
+
+const DEFAULT_LEADERBOARD_SIZE: u32 = 50;
+const MAX_LEADERBOARD_SIZE: u32 = 100;
+
+const DEFAULT_LISTING_SIZE: u32 = 5;
+const MAX_LISTING_SIZE: u32 = 100;
+
+/// most scores a user can have pinned to their profile
+const MAX_PINNED_SCORES: i64 = 50;
+
+/// replays are a few hundred kilobytes for most maps, marathons can get a lot bigger
+const MAX_REPLAY_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Clone, FromRow)]
+pub struct DbScore {
+  pub id: i64,
+  pub user_id: i64,
+  pub beatmap_id: i64,
+  pub ruleset_id: u32,
+  pub passed: bool,
+  pub rank: String,
+  pub total_score: i64,
+  pub accuracy: f64,
+  pub max_combo: u32,
+  pub pp: Option<f64>,
+  pub mods: String,
+  pub statistics: String,
+  pub maximum_statistics: String,
+  pub started_at: Option<DateTime<Utc>>,
+  pub ended_at: DateTime<Utc>,
+  pub has_replay: bool,
+}
+
+/// A score in the client's `SoloScoreInfo` format
+#[derive(Serialize)]
+pub struct ApiScore {
+  id: i64,
+  user_id: i64,
+  beatmap_id: i64,
+  ruleset_id: u32,
+  passed: bool,
+  rank: String,
+  total_score: i64,
+  accuracy: f64,
+  max_combo: u32,
+  pp: Option<f64>,
+  mods: Value,
+  statistics: Value,
+  maximum_statistics: Value,
+  started_at: Option<String>,
+  ended_at: String,
+  has_replay: bool,
+  ranked: bool,
+  preserve: bool,
+  legacy_score_id: Option<i64>,
+  legacy_total_score: i64,
+  #[serde(rename = "type")]
+  kind: &'static str,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  user: Option<ApiUser>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  beatmap: Option<ApiBeatmap>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  beatmapset: Option<ApiBeatmapset>,
+}
+
+impl ApiScore {
+  pub fn new(score: &DbScore) -> Self {
+    let parse = |json: &str| serde_json::from_str(json).unwrap_or(Value::Null);
+
+    Self {
+      id: score.id,
+      user_id: score.user_id,
+      beatmap_id: score.beatmap_id,
+      ruleset_id: score.ruleset_id,
+      passed: score.passed,
+      rank: score.rank.clone(),
+      total_score: score.total_score,
+      accuracy: score.accuracy,
+      max_combo: score.max_combo,
+      pp: score.pp,
+      mods: parse(&score.mods),
+      statistics: parse(&score.statistics),
+      maximum_statistics: parse(&score.maximum_statistics),
+      started_at: score.started_at.map(|date| date.to_rfc3339()),
+      ended_at: score.ended_at.to_rfc3339(),
+      has_replay: score.has_replay,
+      ranked: true,
+      preserve: true,
+      legacy_score_id: None,
+      legacy_total_score: 0,
+      kind: "solo_score",
+      user: None,
+      beatmap: None,
+      beatmapset: None,
+    }
+  }
+
+  pub fn with_user(mut self, user: &User, config: &Config) -> Self {
+    self.user = Some(ApiUser::new(user, config));
+
+    self
+  }
+
+  /// Includes the beatmap and its set, for listings that span multiple beatmaps
+  pub fn with_beatmap(mut self, beatmap: &DbBeatmap, set: &DbBeatmapset) -> Self {
+    self.beatmap = Some(ApiBeatmap::new(beatmap, set));
+    self.beatmapset = Some(ApiBeatmapset::new(set));
+
+    self
+  }
+}
+
+#[derive(Clone, FromRow)]
+struct ScoreToken {
+  id: i64,
+  user_id: i64,
+  beatmap_id: i64,
+  ruleset_id: u32,
+  score_id: Option<i64>,
+  created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+struct ApiScoreToken {
+  id: i64,
+  user_id: i64,
+  beatmap_id: i64,
+  ruleset_id: u32,
+  created_at: String,
+}
+
+#[derive(TryFromMultipart)]
+struct ScoreTokenMultipart {
+  version_hash: Option<String>,
+  beatmap_hash: Option<String>,
+  ruleset_id: Option<u32>,
+}
+
+/// Hands out a token when a play starts, the score is submitted against it once it's over
+async fn create_token(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Path(beatmap_id): Path<i64>,
+  body: TypedMultipart<ScoreTokenMultipart>,
+) -> Result<Json<ApiScoreToken>, Response> {
+  let beatmap = fetch_beatmap(&state, beatmap_id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+    .ok_or_else(|| error(StatusCode::NOT_FOUND, "beatmap not found"))?;
+
+  let Some(beatmap_hash) = body.beatmap_hash.as_deref().filter(|hash| beatmap.checksum.as_deref() == Some(*hash)) else {
+    return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "invalid or missing beatmap_hash"));
+  };
+
+  let Some(ruleset) = body.ruleset_id.and_then(Ruleset::from_id) else {
+    return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "invalid or missing ruleset_id"));
+  };
+
+  // only osu! maps can be converted to other rulesets
+  if beatmap.ruleset_id != Ruleset::Osu.id() && beatmap.ruleset_id != ruleset.id() {
+    return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "invalid ruleset_id"));
+  }
+
+  let token = sqlx::query_as::<_, ScoreToken>(r#"
+    insert into score_tokens (user_id, beatmap_id, ruleset_id, beatmap_hash, version_hash, created_at)
+    values (?, ?, ?, ?, ?, ?)
+    returning *
+  "#)
+    .bind(user.id)
+    .bind(beatmap.id)
+    .bind(ruleset.id())
+    .bind(beatmap_hash)
+    .bind(body.version_hash.as_deref().unwrap_or_default())
+    .bind(Utc::now())
+    .fetch_one(&state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  Ok(Json(ApiScoreToken {
+    id: token.id,
+    user_id: token.user_id,
+    beatmap_id: token.beatmap_id,
+    ruleset_id: token.ruleset_id,
+    created_at: token.created_at.to_rfc3339(),
+  }))
+}
+
+#[derive(Deserialize)]
+struct SubmittedScore {
+  ruleset_id: u32,
+  passed: bool,
+  total_score: i64,
+  accuracy: f64,
+  max_combo: u32,
+  rank: String,
+  #[serde(default)]
+  mods: Vec<Value>,
+  #[serde(default)]
+  statistics: HashMap<String, u32>,
+  #[serde(default)]
+  maximum_statistics: HashMap<String, u32>,
+}
+
+impl SubmittedScore {
+  fn validate(&self, token: &ScoreToken) -> Result<(), &'static str> {
+    if self.ruleset_id != token.ruleset_id {
+      return Err("ruleset doesn't match the score token");
+    }
+
+    if self.total_score < 0 {
+      return Err("invalid total_score");
+    }
+
+    if !(0. ..=1.).contains(&self.accuracy) {
+      return Err("invalid accuracy");
+    }
+
+    if !RANKS.contains(&self.rank.as_str()) {
+      return Err("invalid rank");
+    }
+
+    if !self.passed && self.rank != "F" {
+      return Err("failed scores must have an F rank");
+    }
+
+    Ok(())
+  }
+}
+
+async fn submit_score(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Path((beatmap_id, token_id)): Path<(i64, i64)>,
+  Json(body): Json<SubmittedScore>,
+) -> Result<Json<ApiScore>, Response> {
+  let token = sqlx::query_as::<_, ScoreToken>(r#"
+    select * from score_tokens
+    where id = ?
+  "#)
+    .bind(token_id)
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+    .filter(|token| token.user_id == user.id && token.beatmap_id == beatmap_id)
+    .ok_or_else(|| error(StatusCode::NOT_FOUND, "score token not found"))?;
+
+  if token.score_id.is_some() {
+    return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "score has already been submitted"));
+  }
+
+  body.validate(&token)
+    .map_err(|message| error(StatusCode::UNPROCESSABLE_ENTITY, message))?;
+
+  let beatmap = fetch_beatmap(&state, beatmap_id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+    .ok_or_else(|| error(StatusCode::NOT_FOUND, "beatmap not found"))?;
+
+  let now = Utc::now();
+
+  let ruleset = Ruleset::from_id(token.ruleset_id).unwrap_or(Ruleset::Osu);
+
+  // converts can have a different number of objects and combo, so only the map's own ruleset is checked against it.
+  // these are the judgements with a basic result (great, ok, meh, miss...): osu! judges circles, spinners and
+  // sliders both at their head and as a whole, taiko judges hits and swells but drum rolls only get bonus ticks,
+  // and mania judges both the head and tail of hold notes. catch turns slider repeats into fruits, so it isn't bounded.
+  let own_ruleset = beatmap.ruleset_id == ruleset.id();
+
+  let limits = BeatmapLimits {
+    objects: (own_ruleset && ruleset != Ruleset::Fruits)
+      .then_some(beatmap.count_circles + 2 * beatmap.count_sliders + beatmap.count_spinners),
+    max_combo: own_ruleset.then_some(beatmap.max_combo),
+  };
+
+  let processed = processing::process(
+    ruleset,
+    body.passed,
+    &body.mods,
+    body.max_combo,
+    &body.statistics,
+    &body.maximum_statistics,
+    limits,
+  ).map_err(|message| error(StatusCode::UNPROCESSABLE_ENTITY, message))?;
+
+  if body.total_score > processed.max_total_score {
+    return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "total score is higher than possible"));
+  }
+
+  // pp is only worth calculating for passes that can count towards the user's total
+  let pp = if body.passed && BeatmapStatus::from_id(beatmap.status).awards_pp() {
+    difficulty::score_performance(&state, beatmap_id, ruleset, body.mods.clone(), body.max_combo, body.statistics.clone())
+      .await
+      .unwrap_or_else(|e| {
+        eprintln!("failed to calculate pp on beatmap {}: {:?}", beatmap_id, e);
+        None
+      })
+  } else {
+    None
+  };
+
+  let mut tx = state.pool.begin()
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  let score = sqlx::query_as::<_, DbScore>(r#"
+    insert into scores (
+      user_id, beatmap_id, ruleset_id, passed, rank, total_score, accuracy, max_combo, pp,
+      mods, statistics, maximum_statistics, started_at, ended_at,
+      reported_total_score, reported_accuracy, reported_rank
+    )
+    values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+    returning *
+  "#)
+    .bind(user.id)
+    .bind(beatmap_id)
+    .bind(token.ruleset_id)
+    .bind(body.passed)
+    .bind(processed.rank)
+    .bind(body.total_score)
+    .bind(processed.accuracy)
+    .bind(body.max_combo)
+    .bind(pp)
+    .bind(serde_json::to_string(&body.mods).unwrap_or_else(|_| "[]".into()))
+    .bind(serde_json::to_string(&body.statistics).unwrap_or_else(|_| "{}".into()))
+    .bind(serde_json::to_string(&body.maximum_statistics).unwrap_or_else(|_| "{}".into()))
+    .bind(token.created_at)
+    .bind(now)
+    .bind(body.total_score)
+    .bind(body.accuracy)
+    .bind(&body.rank)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  // claiming the token fails if a concurrent request already used it
+  let claimed = sqlx::query(r#"
+    update score_tokens
+    set score_id = ?
+    where id = ? and score_id is null
+  "#)
+    .bind(score.id)
+    .bind(token.id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  if claimed.rows_affected() == 0 {
+    return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "score has already been submitted"));
+  }
+
+  tx.commit()
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  // half time makes a play last a third longer than the map itself
+  let play_time = (now - token.created_at).num_seconds()
+    .clamp(0, beatmap.total_length as i64 * 4 / 3 + 1);
+
+  if let Err(e) = statistics::record_play(&state.pool, user.id, token.ruleset_id, body.total_score, &body.statistics, play_time).await {
+    eprintln!("failed to update statistics for user {}: {:?}", user.id, e);
+  }
+
+  Ok(Json(ApiScore::new(&score).with_user(&user, &state.config)))
+}
+
+#[derive(Deserialize)]
+struct LeaderboardQuery {
+  #[serde(rename = "type")]
+  kind: Option<String>,
+  mode: Option<String>,
+  /// `pp` ranks by performance instead of total score
+  sort: Option<String>,
+  limit: Option<u32>,
+}
+
+#[derive(Clone, Copy)]
+enum LeaderboardSort {
+  Score,
+  Performance,
+}
+
+impl LeaderboardSort {
+  /// `order by` terms for the scores in `table`, best first
+  fn order(self, table: &str) -> String {
+    match self {
+      Self::Score => format!("{0}.total_score desc, {0}.id", table),
+      Self::Performance => format!("coalesce({0}.pp, 0) desc, {0}.total_score desc, {0}.id", table),
+    }
+  }
+
+  /// Condition for the score in `ahead` placing above the one in `best`
+  fn ahead(self) -> &'static str {
+    match self {
+      Self::Score => r#"
+        ahead.total_score > best.total_score
+        or (ahead.total_score = best.total_score and ahead.id < best.id)
+      "#,
+      Self::Performance => r#"
+        coalesce(ahead.pp, 0) > coalesce(best.pp, 0)
+        or (coalesce(ahead.pp, 0) = coalesce(best.pp, 0) and (
+          ahead.total_score > best.total_score
+          or (ahead.total_score = best.total_score and ahead.id < best.id)
+        ))
+      "#,
+    }
+  }
+}
+
+/// Which scores a leaderboard is built from
+struct LeaderboardFilter {
+  beatmap_id: i64,
+  ruleset_id: u32,
+  country_code: Option<String>,
+  /// only the user and their friends
+  friends_of: Option<i64>,
+  /// acronyms the mods of a score have to match exactly, empty for no mods at all
+  mods: Option<Vec<String>>,
+  sort: LeaderboardSort,
+}
+
+impl LeaderboardFilter {
+  /// Pushes a `best` table holding each user's best score
+  fn push(&self, builder: &mut QueryBuilder<Sqlite>) {
+    builder.push(format!(r#"
+      with best as (
+        select * from (
+          select
+            sc.*,
+            row_number() over (partition by sc.user_id order by {}) as n
+          from scores sc
+          join users u on u.id = sc.user_id
+          where sc.passed and sc.beatmap_id = "#, self.sort.order("sc")))
+      .push_bind(self.beatmap_id)
+      .push(" and sc.ruleset_id = ")
+      .push_bind(self.ruleset_id);
+
+    if let Some(country_code) = &self.country_code {
+      builder.push(" and u.country_code = ").push_bind(country_code.clone());
+    }
+
+    if let Some(user_id) = self.friends_of {
+      builder.push(" and (sc.user_id = ")
+        .push_bind(user_id)
+        .push(" or sc.user_id in (select target_id from relations where relation_type = 0 and user_id = ")
+        .push_bind(user_id)
+        .push("))");
+    }
+
+    match &self.mods {
+      Some(mods) if mods.is_empty() => {
+        builder.push(" and json_array_length(sc.mods) = 0");
+      },
+      Some(mods) => {
+        builder.push(" and json_array_length(sc.mods) = ")
+          .push_bind(mods.len() as i64)
+          .push(" and not exists (select 1 from json_each(sc.mods) m where json_extract(m.value, '$.acronym') not in (");
+
+        let mut list = builder.separated(", ");
+        for acronym in mods {
+          list.push_bind(acronym.clone());
+        }
+
+        builder.push("))");
+      },
+      None => {},
+    }
+
+    builder.push(") where n = 1)");
+  }
+}
+
+#[derive(Serialize)]
+struct UserScore {
+  position: i64,
+  score: ApiScore,
+}
+
+#[derive(Serialize)]
+struct Leaderboard {
+  scores: Vec<ApiScore>,
+  user_score: Option<UserScore>,
+  score_count: i64,
+}
+
+#[derive(FromRow)]
+struct PositionedScore {
+  #[sqlx(flatten)]
+  score: DbScore,
+  position: i64,
+}
+
+/// Best score of every user on a beatmap, along with where the caller's own best score places
+async fn leaderboard(
+  State(state): State<FiberState>,
+  user: Option<Extension<User>>,
+  Path(beatmap_id): Path<i64>,
+  Query(query): Query<LeaderboardQuery>,
+  RawQuery(raw_query): RawQuery,
+) -> Result<Json<Leaderboard>, Response> {
+  let user = user.map(|Extension(user)| user);
+
+  let beatmap = fetch_beatmap(&state, beatmap_id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+    .ok_or_else(|| error(StatusCode::NOT_FOUND, "beatmap not found"))?;
+
+  let ruleset = match query.mode.as_deref() {
+    Some(mode) => Ruleset::from_name(mode).ok_or_else(|| error(StatusCode::UNPROCESSABLE_ENTITY, "invalid mode"))?,
+    None => Ruleset::from_id(beatmap.ruleset_id).unwrap_or(Ruleset::Osu),
+  };
+
+  if !BeatmapStatus::from_id(beatmap.status).has_leaderboard() {
+    return Ok(Json(Leaderboard {
+      scores: vec![],
+      user_score: None,
+      score_count: 0,
+    }));
+  }
+
+  let mut filter = LeaderboardFilter {
+    beatmap_id,
+    ruleset_id: ruleset.id(),
+    country_code: None,
+    friends_of: None,
+    mods: None,
+    sort: match query.sort.as_deref() {
+      Some("pp") => LeaderboardSort::Performance,
+      _ => LeaderboardSort::Score,
+    },
+  };
+
+  match (query.kind.as_deref().unwrap_or("global"), &user) {
+    ("global", _) => {},
+    ("country", Some(user)) => filter.country_code = Some(user.country_code.clone()),
+    ("friend", Some(user)) => filter.friends_of = Some(user.id),
+    ("country" | "friend", None) => return Err(error(StatusCode::UNAUTHORIZED, "this leaderboard needs a user")),
+    _ => return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "invalid type")),
+  }
+
+  let mods = query_list(raw_query.as_deref().unwrap_or_default(), "mods");
+
+  if !mods.is_empty() {
+    // NM stands for playing without any mods
+    filter.mods = Some(mods.into_iter().filter(|acronym| acronym != "NM").collect());
+  }
+
+  let limit = query.limit.unwrap_or(DEFAULT_LEADERBOARD_SIZE).clamp(1, MAX_LEADERBOARD_SIZE);
+
+  let mut builder = QueryBuilder::<Sqlite>::new("");
+  filter.push(&mut builder);
+  builder.push(format!(" select * from best order by {} limit ", filter.sort.order("best")))
+    .push_bind(limit);
+
+  let scores = builder.build_query_as::<DbScore>()
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| {
+      eprintln!("{:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })?;
+
+  let mut builder = QueryBuilder::<Sqlite>::new("");
+  filter.push(&mut builder);
+  builder.push(" select count(*) from best");
+
+  let score_count = builder.build_query_scalar::<i64>()
+    .fetch_one(&state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  let mut user_score = None;
+
+  if let Some(user) = &user {
+    let mut builder = QueryBuilder::<Sqlite>::new("");
+    filter.push(&mut builder);
+    builder.push(" select *, (select count(*) + 1 from best ahead where ")
+      .push(filter.sort.ahead())
+      .push(") as position from best where user_id = ")
+      .push_bind(user.id);
+
+    let positioned = builder.build_query_as::<PositionedScore>()
+      .fetch_optional(&state.pool)
+      .await
+      .map_err(|e| {
+        eprintln!("{:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+      })?;
+
+    user_score = positioned.map(|positioned| UserScore {
+      position: positioned.position,
+      score: ApiScore::new(&positioned.score).with_user(user, &state.config),
+    });
+  }
+
+  // the users of the listed scores, joined on the same ranking
+  let mut builder = QueryBuilder::<Sqlite>::new("");
+  filter.push(&mut builder);
+  builder.push(format!(" select u.* from best join users u on u.id = best.user_id order by {} limit ", filter.sort.order("best")))
+    .push_bind(limit);
+
+  let users = builder.build_query_as::<User>()
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+    .into_iter()
+    .map(|user| (user.id, user))
+    .collect::<HashMap<_, _>>();
+
+  let scores = scores.iter()
+    .filter_map(|score| Some(ApiScore::new(score).with_user(users.get(&score.user_id)?, &state.config)))
+    .collect();
+
+  Ok(Json(Leaderboard {
+    scores,
+    user_score,
+    score_count,
+  }))
+}
+
+#[derive(Deserialize)]
+struct UserScoresQuery {
+  mode: Option<String>,
+  include_fails: Option<String>,
+  limit: Option<u32>,
+  offset: Option<u32>,
+}
+
+/// A user's best, recent, first place or pinned scores, the way their profile lists them
+async fn user_scores(
+  State(state): State<FiberState>,
+  Path((user_id, kind)): Path<(i64, String)>,
+  Query(query): Query<UserScoresQuery>,
+) -> Result<Json<Vec<ApiScore>>, Response> {
+  let user = sqlx::query_as::<_, User>(r#"
+    select * from users
+    where id = ?
+  "#)
+    .bind(user_id)
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+    .ok_or_else(|| error(StatusCode::NOT_FOUND, "user not found"))?;
+
+  let ruleset = match query.mode.as_deref() {
+    Some(mode) => Ruleset::from_name(mode).ok_or_else(|| error(StatusCode::UNPROCESSABLE_ENTITY, "invalid mode"))?,
+    None => Ruleset::Osu,
+  };
+
+  let limit = query.limit.unwrap_or(DEFAULT_LISTING_SIZE).clamp(1, MAX_LISTING_SIZE);
+  let offset = query.offset.unwrap_or(0);
+  let include_fails = matches!(query.include_fails.as_deref(), Some("1" | "true"));
+
+  let sql = match kind.as_str() {
+    // the best pp play on each map that awards it
+    "best" => r#"
+      select * from (
+        select
+          sc.*,
+          row_number() over (partition by sc.beatmap_id order by sc.pp desc, sc.id) as n
+        from scores sc
+        join beatmaps b on b.id = sc.beatmap_id
+        where sc.user_id = ?1 and sc.ruleset_id = ?2 and sc.passed and sc.pp is not null and b.status in (select value from json_each(?7))
+      )
+      where n = 1
+      order by pp desc, id
+      limit ?3 offset ?4
+    "#,
+    "recent" => r#"
+      select * from scores
+      where user_id = ?1 and ruleset_id = ?2 and (passed or ?5) and ended_at > ?6
+      order by ended_at desc, id desc
+      limit ?3 offset ?4
+    "#,
+    // scores on top of a leaderboard, ranked by total score like the default leaderboard
+    "firsts" => r#"
+      select * from (
+        select
+          sc.*,
+          row_number() over (partition by sc.beatmap_id order by sc.total_score desc, sc.id) as n
+        from scores sc
+        join beatmaps b on b.id = sc.beatmap_id
+        where sc.ruleset_id = ?2 and sc.passed and b.status in (select value from json_each(?8))
+          and sc.beatmap_id in (select beatmap_id from scores where user_id = ?1 and ruleset_id = ?2 and passed)
+      )
+      where n = 1 and user_id = ?1
+      order by ended_at desc, id desc
+      limit ?3 offset ?4
+    "#,
+    "pinned" => r#"
+      select sc.* from score_pins p
+      join scores sc on sc.id = p.score_id
+      where p.user_id = ?1 and sc.ruleset_id = ?2
+      order by p.display_order, p.score_id
+      limit ?3 offset ?4
+    "#,
+    _ => return Err(error(StatusCode::NOT_FOUND, "unknown score type")),
+  };
+
+  let scores = sqlx::query_as::<_, DbScore>(sql)
+    .bind(user.id)
+    .bind(ruleset.id())
+    .bind(limit)
+    .bind(offset)
+    .bind(include_fails)
+    .bind(Utc::now() - TimeDelta::days(1))
+    .bind(BeatmapStatus::ids_where(BeatmapStatus::awards_pp))
+    .bind(BeatmapStatus::ids_where(BeatmapStatus::has_leaderboard))
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| {
+      eprintln!("{:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })?;
+
+  let beatmap_ids = scores.iter().map(|score| score.beatmap_id).collect::<Vec<_>>();
+
+  let beatmaps = fetch_beatmaps(&state, &beatmap_ids)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  let set_ids = beatmaps.values().map(|beatmap| beatmap.beatmapset_id).collect::<Vec<_>>();
+
+  let sets = fetch_beatmapsets(&state, &set_ids)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  Ok(Json(scores.iter()
+    .filter_map(|score| {
+      let beatmap = beatmaps.get(&score.beatmap_id)?;
+      let set = sets.get(&beatmap.beatmapset_id)?;
+
+      Some(ApiScore::new(score).with_user(&user, &state.config).with_beatmap(beatmap, set))
+    })
+    .collect()))
+}
+
+/// Pins one of the user's own passes to the top of their profile
+async fn pin_score(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Path(score_id): Path<i64>,
+) -> Result<StatusCode, Response> {
+  let score = fetch_score(&state, score_id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+    .filter(|score| score.user_id == user.id)
+    .ok_or_else(|| error(StatusCode::NOT_FOUND, "score not found"))?;
+
+  if !score.passed {
+    return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "only passed scores can be pinned"));
+  }
+
+  let (pinned, first) = sqlx::query_as::<_, (i64, i64)>(r#"
+    select count(*), coalesce(min(display_order), 0) from score_pins
+    where user_id = ?
+  "#)
+    .bind(user.id)
+    .fetch_one(&state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  if pinned >= MAX_PINNED_SCORES {
+    return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "too many pinned scores"));
+  }
+
+  // pinning a score that's already pinned leaves it where it is
+  sqlx::query(r#"
+    insert into score_pins (score_id, user_id, display_order)
+    values (?, ?, ?)
+    on conflict (score_id) do nothing
+  "#)
+    .bind(score.id)
+    .bind(user.id)
+    .bind(first - 1)
+    .execute(&state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  Ok(StatusCode::NO_CONTENT)
+}
+
+async fn unpin_score(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Path(score_id): Path<i64>,
+) -> Result<StatusCode, StatusCode> {
+  sqlx::query(r#"
+    delete from score_pins
+    where score_id = ? and user_id = ?
+  "#)
+    .bind(score_id)
+    .bind(user.id)
+    .execute(&state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+  Ok(StatusCode::NO_CONTENT)
+}
+
+async fn fetch_score(state: &FiberState, id: i64) -> sqlx::Result<Option<DbScore>> {
+  sqlx::query_as::<_, DbScore>(r#"
+    select * from scores
+    where id = ?
+  "#)
+    .bind(id)
+    .fetch_optional(&state.pool)
+    .await
+}
+
+/// Stores the replay of one of the user's own scores, it can only be uploaded once
+async fn upload_replay(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Path(score_id): Path<i64>,
+  body: Bytes,
+) -> Result<StatusCode, Response> {
+  let score = fetch_score(&state, score_id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+    .ok_or_else(|| error(StatusCode::NOT_FOUND, "score not found"))?;
+
+  if score.user_id != user.id {
+    return Err(error(StatusCode::FORBIDDEN, "replays can only be uploaded for your own scores"));
+  }
+
+  if score.has_replay {
+    return Err(error(StatusCode::CONFLICT, "score already has a replay"));
+  }
+
+  let header = ReplayHeader::parse(&body)
+    .map_err(|_| error(StatusCode::UNPROCESSABLE_ENTITY, "invalid replay"))?;
+
+  let beatmap = fetch_beatmap(&state, score.beatmap_id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+    .ok_or_else(|| error(StatusCode::NOT_FOUND, "beatmap not found"))?;
+
+  let matches = header.ruleset_id as u32 == score.ruleset_id
+    && beatmap.checksum.as_deref() == Some(header.beatmap_hash.as_str())
+    && header.username == user.username
+    && header.max_combo as u32 == score.max_combo.min(u16::MAX as u32);
+
+  if !matches {
+    return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "replay doesn't match the score"));
+  }
+
+  state.replays.put(score.id, &body)
+    .await
+    .map_err(|e| {
+      eprintln!("failed to store replay of score {}: {:?}", score.id, e);
+      StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })?;
+
+  sqlx::query(r#"
+    update scores
+    set has_replay = true
+    where id = ?
+  "#)
+    .bind(score.id)
+    .execute(&state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  Ok(StatusCode::NO_CONTENT)
+}
+
+/// Serves a score's replay, counting the view for its player when someone else watches it
+async fn download_replay(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Path(score_id): Path<i64>,
+  headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+  let score = fetch_score(&state, score_id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .filter(|score| score.has_replay)
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+  let data = state.replays.get(score.id)
+    .await
+    .map_err(|e| {
+      eprintln!("{:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+  // every user counts once per score, however many times they download it
+  if score.user_id != user.id {
+    let mut tx = state.pool.begin()
+      .await
+      .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let viewed = sqlx::query(r#"
+      insert or ignore into replay_views (score_id, user_id)
+      values (?, ?)
+    "#)
+      .bind(score.id)
+      .bind(user.id)
+      .execute(&mut *tx)
+      .await
+      .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if viewed.rows_affected() == 1 {
+      sqlx::query(r#"
+        update statistics
+        set replays_watched_by_others = replays_watched_by_others + 1
+        where user_id = ? and ruleset_id = ?
+      "#)
+        .bind(score.user_id)
+        .bind(score.ruleset_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+
+    tx.commit()
+      .await
+      .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+  }
+
+  let ruleset = Ruleset::from_id(score.ruleset_id).unwrap_or(Ruleset::Osu);
+  let filename = format!("replay-{}_{}_{}.osr", ruleset.name(), score.beatmap_id, score.id);
+
+  Ok(attachment(&headers, data, "application/x-osu-replay", &filename))
+}
+
+pub fn router(state: FiberState) -> Router<FiberState> {
+  let public = Router::new()
+    .route("/api/v2/beatmaps/{id}/scores", get(leaderboard))
+    .route("/api/v2/beatmaps/{id}/solo-scores", get(leaderboard))
+    .route("/api/v2/users/{id}/scores/{type}", get(user_scores))
+    .layer(middleware::from_fn_with_state(state.clone(), auth::public_middleware));
+
+  Router::new()
+    .route("/api/v2/beatmaps/{id}/solo/scores", post(create_token))
+    .route("/api/v2/beatmaps/{id}/solo/scores/{token}", put(submit_score))
+    .route("/api/v2/scores/{id}/replay", put(upload_replay).layer(DefaultBodyLimit::max(MAX_REPLAY_SIZE)))
+    .route("/api/v2/scores/{id}/download", get(download_replay))
+    .route("/api/v2/score-pins/{id}", put(pin_score).delete(unpin_score))
+    .layer(middleware::from_fn_with_state((state, "*"), auth::middleware))
+    .merge(public)
+}
\ No newline at end of file
@@ -0,0 +1,429 @@
+use axum::{extract::{Query, State}, http::StatusCode, middleware, routing::get, Extension, Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{QueryBuilder, Row, Sqlite};
+
+use crate::{auth::{self, User}, beatmaps::status::BeatmapStatus, routes::beatmaps::{fetch_beatmaps_of_set, fetch_beatmapset, ApiBeatmapset}, ruleset::Ruleset, state::FiberState};
+
+const PAGE_SIZE: i64 = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortField {
+  Title,
+  Artist,
+  Difficulty,
+  Ranked,
+  Updated,
+  Plays,
+  Favourites,
+  Rating,
+  Relevance,
+}
+
+impl SortField {
+  fn from_name(name: &str) -> Option<Self> {
+    Some(match name {
+      "title" => Self::Title,
+      "artist" => Self::Artist,
+      "difficulty" => Self::Difficulty,
+      "ranked" => Self::Ranked,
+      "updated" => Self::Updated,
+      "plays" => Self::Plays,
+      "favourites" => Self::Favourites,
+      "rating" => Self::Rating,
+      "relevance" => Self::Relevance,
+      _ => return None,
+    })
+  }
+
+  fn name(self) -> &'static str {
+    match self {
+      Self::Title => "title",
+      Self::Artist => "artist",
+      Self::Difficulty => "difficulty",
+      Self::Ranked => "ranked",
+      Self::Updated => "updated",
+      Self::Plays => "plays",
+      Self::Favourites => "favourites",
+      Self::Rating => "rating",
+      Self::Relevance => "relevance",
+    }
+  }
+
+  /// Value the results are ordered by, never null so it can be compared against a cursor
+  fn expression(self) -> &'static str {
+    match self {
+      Self::Title => "lower(s.title)",
+      Self::Artist => "lower(s.artist)",
+      Self::Difficulty => "(select coalesce(max(b.difficulty_rating), 0) from beatmaps b where b.beatmapset_id = s.id)",
+      Self::Ranked => "coalesce(s.ranked_at, '')",
+      Self::Updated => "coalesce(s.updated_at, '')",
+      Self::Plays => "(select count(*) from scores sc join beatmaps b on b.id = sc.beatmap_id where b.beatmapset_id = s.id)",
+      // there's no favourites or ratings yet, so these only order by id
+      Self::Favourites | Self::Rating => "0",
+      // bm25 is lower for better matches
+      Self::Relevance => "-bm25(beatmapsets_fts)",
+    }
+  }
+
+  fn is_text(self) -> bool {
+    matches!(self, Self::Title | Self::Artist | Self::Ranked | Self::Updated)
+  }
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+  q: Option<String>,
+  /// ruleset id
+  m: Option<u32>,
+  /// status
+  s: Option<String>,
+  /// genre id
+  g: Option<i64>,
+  /// language id
+  l: Option<i64>,
+  /// extras, `video` and `storyboard` separated by dots
+  e: Option<String>,
+  sort: Option<String>,
+  cursor_string: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+  value: Value,
+  id: i64,
+}
+
+impl Cursor {
+  fn encode(&self) -> String {
+    hex::encode(serde_json::to_vec(self).unwrap_or_default())
+  }
+
+  fn decode(cursor: &str) -> Option<Self> {
+    let data = hex::decode(cursor).ok()?;
+
+    serde_json::from_slice(&data).ok()
+  }
+}
+
+/// A numeric beatmap attribute filter from the query text, like `stars>5`
+struct RangeFilter {
+  column: &'static str,
+  operator: &'static str,
+  value: f64,
+}
+
+#[derive(Default)]
+struct Filters {
+  /// fts5 match expression
+  text: Option<String>,
+  ruleset: Option<Ruleset>,
+  statuses: Option<Vec<BeatmapStatus>>,
+  genre: Option<i64>,
+  language: Option<i64>,
+  video: bool,
+  storyboard: bool,
+  creator_id: Option<i64>,
+  ranges: Vec<RangeFilter>,
+  /// searching for something we don't keep track of, like favourites
+  nothing: bool,
+}
+
+/// Quotes a word for fts5 and lets it match as a prefix
+fn fts_term(word: &str) -> Option<String> {
+  word.chars()
+    .any(char::is_alphanumeric)
+    .then(|| format!("\"{}\"*", word.replace('"', "\"\"")))
+}
+
+fn beatmap_column(key: &str) -> Option<&'static str> {
+  Some(match key {
+    "stars" | "star" | "sr" => "difficulty_rating",
+    "length" => "total_length",
+    "ar" => "ar",
+    "cs" => "cs",
+    "od" | "accuracy" => "od",
+    "hp" | "drain" => "drain",
+    "bpm" => "bpm",
+    _ => return None,
+  })
+}
+
+fn status_filter(status: &str) -> Option<Vec<BeatmapStatus>> {
+  use BeatmapStatus::*;
+
+  Some(match status {
+    "any" => return None,
+    "leaderboard" => vec![Ranked, Approved, Qualified, Loved],
+    "ranked" => vec![Ranked, Approved],
+    status => vec![BeatmapStatus::from_name(status)?],
+  })
+}
+
+impl Filters {
+  fn new(query: &SearchQuery, user: Option<&User>) -> Self {
+    let mut filters = Self {
+      ruleset: query.m.and_then(Ruleset::from_id),
+      genre: query.g.filter(|id| *id > 0),
+      language: query.l.filter(|id| *id > 0),
+      ..Default::default()
+    };
+
+    for extra in query.e.as_deref().unwrap_or_default().split('.') {
+      match extra {
+        "video" => filters.video = true,
+        "storyboard" => filters.storyboard = true,
+        _ => {},
+      }
+    }
+
+    match query.s.as_deref().unwrap_or("leaderboard") {
+      "mine" => match user {
+        Some(user) => filters.creator_id = Some(user.id),
+        None => filters.nothing = true,
+      },
+      "favourites" => filters.nothing = true,
+      status => filters.statuses = status_filter(status),
+    }
+
+    filters.parse_text(query.q.as_deref().unwrap_or_default());
+
+    filters
+  }
+
+  /// Splits `key=value` style filters from the words that are searched for
+  fn parse_text(&mut self, q: &str) {
+    let mut terms = vec![];
+
+    for word in q.split_whitespace() {
+      let filter = [">=", "<=", ">", "<", "=", ":"].into_iter()
+        .filter_map(|operator| word.find(operator).map(|i| (i, operator)))
+        .min_by_key(|(i, operator)| (*i, -(operator.len() as i32)))
+        .map(|(i, operator)| (word[..i].to_lowercase(), operator, &word[i + operator.len()..]));
+
+      let Some((key, operator, value)) = filter else {
+        terms.extend(fts_term(word));
+        continue
+      };
+
+      if let (Some(column), Ok(value)) = (beatmap_column(&key), value.parse()) {
+        let operator = match operator {
+          ":" => "=",
+          operator => operator,
+        };
+
+        self.ranges.push(RangeFilter { column, operator, value });
+        continue;
+      }
+
+      match key.as_str() {
+        "artist" | "title" | "creator" | "source" | "tags" => {
+          if let Some(term) = fts_term(value) {
+            terms.push(format!("{} : {}", key, term));
+          }
+        },
+        "status" => {
+          if let Some(statuses) = status_filter(value) {
+            self.statuses = Some(statuses);
+          }
+        },
+        _ => terms.extend(fts_term(word)),
+      }
+    }
+
+    self.text = (!terms.is_empty()).then(|| terms.join(" "));
+  }
+
+  /// Appends the `from` and `where` clauses
+  fn push(&self, builder: &mut QueryBuilder<Sqlite>) {
+    builder.push(" from beatmapsets s");
+
+    if let Some(text) = &self.text {
+      builder.push(" join beatmapsets_fts on beatmapsets_fts.rowid = s.id where beatmapsets_fts match ");
+      builder.push_bind(text.clone());
+    } else {
+      builder.push(" where 1");
+    }
+
+    if self.nothing {
+      builder.push(" and 0");
+    }
+
+    if let Some(statuses) = &self.statuses {
+      builder.push(" and s.status in (");
+      let mut list = builder.separated(", ");
+      for status in statuses {
+        list.push_bind(status.id());
+      }
+      builder.push(")");
+    }
+
+    if let Some(genre) = self.genre {
+      builder.push(" and s.genre_id = ").push_bind(genre);
+    }
+
+    if let Some(language) = self.language {
+      builder.push(" and s.language_id = ").push_bind(language);
+    }
+
+    if self.video {
+      builder.push(" and s.has_video");
+    }
+
+    if self.storyboard {
+      builder.push(" and s.has_storyboard");
+    }
+
+    if let Some(creator_id) = self.creator_id {
+      builder.push(" and s.creator_id = ").push_bind(creator_id);
+    }
+
+    // sets need at least one difficulty that matches every beatmap filter
+    builder.push(" and exists (select 1 from beatmaps b where b.beatmapset_id = s.id");
+
+    if let Some(ruleset) = self.ruleset {
+      builder.push(" and b.ruleset_id = ").push_bind(ruleset.id());
+    }
+
+    for range in &self.ranges {
+      if range.operator == "=" {
+        // exact floats rarely match, so `=` allows for rounding
+        builder.push(format!(" and abs(b.{} - ", range.column))
+          .push_bind(range.value)
+          .push(") < 0.05");
+      } else {
+        builder.push(format!(" and b.{} {} ", range.column, range.operator))
+          .push_bind(range.value);
+      }
+    }
+
+    builder.push(")");
+  }
+}
+
+#[derive(Serialize)]
+struct SearchInfo {
+  sort: String,
+}
+
+#[derive(Serialize)]
+struct SearchResponse {
+  beatmapsets: Vec<ApiBeatmapset>,
+  cursor_string: Option<String>,
+  search: SearchInfo,
+  recommended_difficulty: Option<f32>,
+  error: Option<String>,
+  total: i64,
+}
+
+async fn search(
+  State(state): State<FiberState>,
+  user: Option<Extension<User>>,
+  Query(query): Query<SearchQuery>,
+) -> Result<Json<SearchResponse>, StatusCode> {
+  let filters = Filters::new(&query, user.as_ref().map(|Extension(user)| user));
+
+  let default_sort = match filters.text {
+    Some(_) => (SortField::Relevance, true),
+    None => (SortField::Ranked, true),
+  };
+
+  let (sort, descending) = query.sort.as_deref()
+    .and_then(|sort| sort.rsplit_once('_'))
+    .and_then(|(field, direction)| Some((SortField::from_name(field)?, direction == "desc")))
+    // relevance doesn't mean anything without text to match
+    .filter(|(field, _)| *field != SortField::Relevance || filters.text.is_some())
+    .unwrap_or(default_sort);
+
+  let mut count = QueryBuilder::<Sqlite>::new("select count(*)");
+  filters.push(&mut count);
+
+  let total = count.build_query_scalar::<i64>()
+    .fetch_one(&state.pool)
+    .await
+    .map_err(|e| {
+      eprintln!("{:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+  let mut builder = QueryBuilder::<Sqlite>::new("select * from (select s.id, ");
+  if sort.is_text() {
+    builder.push(sort.expression());
+  } else {
+    builder.push(format!("cast({} as real)", sort.expression()));
+  }
+  builder.push(" as sort_value");
+  filters.push(&mut builder);
+  builder.push(")");
+
+  let cursor = query.cursor_string.as_deref().and_then(Cursor::decode);
+
+  if let Some(cursor) = &cursor {
+    builder.push(if descending { " where (sort_value, id) < (" } else { " where (sort_value, id) > (" });
+
+    match &cursor.value {
+      Value::String(value) => builder.push_bind(value.clone()),
+      value => builder.push_bind(value.as_f64().unwrap_or_default()),
+    };
+
+    builder.push(", ").push_bind(cursor.id).push(")");
+  }
+
+  let direction = if descending { "desc" } else { "asc" };
+  builder.push(format!(" order by sort_value {0}, id {0} limit ", direction))
+    .push_bind(PAGE_SIZE + 1);
+
+  let mut rows = builder.build()
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| {
+      eprintln!("{:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+  // the extra row only tells us whether there's another page
+  let has_more = rows.len() as i64 > PAGE_SIZE;
+  rows.truncate(PAGE_SIZE as usize);
+
+  let mut beatmapsets = vec![];
+  let mut next_cursor = None;
+
+  for row in &rows {
+    let id = row.get::<i64, _>("id");
+
+    let value = if sort.is_text() {
+      Value::from(row.get::<String, _>("sort_value"))
+    } else {
+      Value::from(row.get::<f64, _>("sort_value"))
+    };
+
+    next_cursor = Some(Cursor { value, id });
+
+    let Some(set) = fetch_beatmapset(&state, id).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)? else {
+      continue
+    };
+
+    let beatmaps = fetch_beatmaps_of_set(&state, id)
+      .await
+      .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    beatmapsets.push(ApiBeatmapset::new(&set).with_beatmaps(&set, &beatmaps));
+  }
+
+  Ok(Json(SearchResponse {
+    beatmapsets,
+    cursor_string: next_cursor.filter(|_| has_more).map(|cursor| cursor.encode()),
+    search: SearchInfo {
+      sort: format!("{}_{}", sort.name(), direction),
+    },
+    recommended_difficulty: None,
+    error: None,
+    total,
+  }))
+}
+
+pub fn router(state: FiberState) -> Router<FiberState> {
+  Router::new()
+    .route("/api/v2/beatmapsets/search", get(search))
+    .layer(middleware::from_fn_with_state(state, auth::public_middleware))
+}
\ No newline at end of file
@@ -0,0 +1,139 @@
+use axum::{extract::State, http::StatusCode, middleware, response::{IntoResponse, Response}, routing::post, Extension, Router};
+use axum_typed_multipart::{TryFromMultipart, TypedMultipart};
+use chrono::{Duration, Utc};
+use rand::Rng;
+
+use crate::{auth::{self, Token, User}, mail, routes::error, state::FiberState};
+
+/// how long a verification code can be used after it was sent
+const CODE_LIFETIME: Duration = Duration::hours(1);
+
+/// wrong guesses allowed before a code has to be reissued
+const MAX_ATTEMPTS: i64 = 5;
+
+/// minimum time between two codes being sent for the same session
+const REISSUE_COOLDOWN: Duration = Duration::seconds(30);
+
+fn generate_code() -> String {
+  let mut rng = rand::rng();
+
+  (0..8)
+    .map(|_| char::from(b'0' + rng.random_range(0..10)))
+    .collect()
+}
+
+/// Generates a new verification code for a session and mails it to the user
+pub async fn send_verification_code(state: &FiberState, token_id: i64, user: &User) -> anyhow::Result<()> {
+  let code = generate_code();
+
+  sqlx::query(r#"
+    update oauth_tokens
+    set verification_code = ?, verification_sent_at = ?, verification_attempts = 0
+    where id = ?
+  "#)
+    .bind(&code)
+    .bind(Utc::now())
+    .bind(token_id)
+    .execute(&state.pool)
+    .await?;
+
+  let email = sqlx::query_scalar::<_, Option<String>>(r#"
+    select email from users
+    where id = ?
+  "#)
+    .bind(user.id)
+    .fetch_one(&state.pool)
+    .await?;
+
+  let to = match email {
+    Some(email) => format!("{} <{}>", user.username, email),
+    None => user.username.clone(),
+  };
+
+  let body = format!(
+    "Hi {},\n\nSomeone signed in to your account. If it was you, enter this code in the client to verify the session:\n\n    {}\n\nThe code expires in {} minutes.",
+    user.username,
+    code,
+    CODE_LIFETIME.num_minutes(),
+  );
+
+  mail::send(&state.config, &to, "Account verification code", &body).await
+}
+
+#[derive(TryFromMultipart)]
+struct VerifyMultipart {
+  verification_key: String,
+}
+
+async fn verify(
+  State(state): State<FiberState>,
+  Extension(token): Extension<Token>,
+  body: TypedMultipart<VerifyMultipart>,
+) -> Response {
+  if token.verified {
+    return StatusCode::OK.into_response();
+  }
+
+  let (Some(code), Some(sent_at)) = (&token.verification_code, token.verification_sent_at) else {
+    return error(StatusCode::UNPROCESSABLE_ENTITY, "No verification code was sent. Please request a new one.");
+  };
+
+  if sent_at + CODE_LIFETIME < Utc::now() || token.verification_attempts >= MAX_ATTEMPTS {
+    return error(StatusCode::UNPROCESSABLE_ENTITY, "Verification code expired. Please request a new one.");
+  }
+
+  if body.verification_key.trim() != code {
+    let _ = sqlx::query(r#"
+      update oauth_tokens
+      set verification_attempts = verification_attempts + 1
+      where id = ?
+    "#)
+      .bind(token.id)
+      .execute(&state.pool)
+      .await;
+
+    return error(StatusCode::UNPROCESSABLE_ENTITY, "Incorrect verification code.");
+  }
+
+  match sqlx::query(r#"
+    update oauth_tokens
+    set verified = true, verification_code = null
+    where id = ?
+  "#)
+    .bind(token.id)
+    .execute(&state.pool)
+    .await
+  {
+    Ok(_) => StatusCode::OK.into_response(),
+    Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+  }
+}
+
+async fn reissue(
+  State(state): State<FiberState>,
+  Extension(token): Extension<Token>,
+  Extension(user): Extension<User>,
+) -> Response {
+  if token.verified {
+    return error(StatusCode::UNPROCESSABLE_ENTITY, "Session is already verified.");
+  }
+
+  if token.verification_sent_at.is_some_and(|sent_at| sent_at + REISSUE_COOLDOWN > Utc::now()) {
+    return error(StatusCode::TOO_MANY_REQUESTS, "Please wait a bit before requesting another code.");
+  }
+
+  match send_verification_code(&state, token.id, &user).await {
+    Ok(_) => StatusCode::OK.into_response(),
+    Err(e) => {
+      eprintln!("{:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    },
+  }
+}
+
+pub fn router(state: FiberState) -> Router<FiberState> {
+  Router::new()
+    .route("/api/v2/session/verify", post(verify))
+    .route("/api/v2/session/verify/reissue", post(reissue))
+    .layer(middleware::from_fn_with_state((state, "identify"), auth::unverified_middleware))
+}
\ No newline at end of file
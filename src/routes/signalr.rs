@@ -1,17 +1,137 @@
-use axum::{body::Body, extract::{Path, WebSocketUpgrade}, http::StatusCode, response::{IntoResponse, Response}, routing::{get, post}, Json, Router};
-use serde::Serialize;
+use std::convert::Infallible;
 
-use crate::{signalr::hub::{metadata::handle_metadata_hub, multiplayer::handle_multiplayer_hub, spectator::handle_spectator_hub}, state::FiberState};
+use axum::{body::{Body, Bytes}, extract::{ws::rejection::WebSocketUpgradeRejection, Path, Query, State, WebSocketUpgrade}, http::{header, HeaderMap, StatusCode}, middleware, response::{sse::{Event, KeepAlive}, IntoResponse, Response, Sse}, routing::{get, post}, Extension, Json, Router};
+use serde::{Deserialize, Serialize};
+use tokio_stream::{wrappers::UnboundedReceiverStream, StreamExt};
+
+use crate::{auth::{self, User}, signalr::{connection::PollResult, hub::{handle_hub, Hub, SignalRProtocol}, transport::{pump_websocket, HubSocket, Transport}}, state::FiberState};
+
+#[derive(Deserialize)]
+struct HubQuery {
+  id: Option<String>,
+}
+
+/// Closes a server-sent events connection once its stream is dropped
+struct ConnectionGuard {
+  state: FiberState,
+  token: String,
+  user_id: i64,
+}
+
+impl Drop for ConnectionGuard {
+  fn drop(&mut self) {
+    self.state.signalr.close(&self.token, self.user_id);
+  }
+}
 
 async fn signalr_hub(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
   Path(hub): Path<String>,
-  ws: WebSocketUpgrade
+  Query(query): Query<HubQuery>,
+  headers: HeaderMap,
+  ws: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
 ) -> Response<Body> {
-  match hub.as_str() {
-    "metadata" => ws.on_upgrade(handle_metadata_hub),
-    "multiplayer" => ws.on_upgrade(handle_multiplayer_hub),
-    "spectator" => ws.on_upgrade(handle_spectator_hub),
-    _ => (StatusCode::NOT_FOUND).into_response(),
+  let Some(hub) = Hub::from_name(&hub) else {
+    return StatusCode::NOT_FOUND.into_response();
+  };
+
+  let Some(token) = query.id else {
+    return (StatusCode::BAD_REQUEST, "Connection ID required").into_response();
+  };
+
+  // subsequent long polling requests
+  if state.signalr.is_active(&token, user.id) {
+    return match state.signalr.poll(&token, user.id).await {
+      Some(PollResult::Data(data)) => data.into_response(),
+      Some(PollResult::Timeout) => StatusCode::OK.into_response(),
+      Some(PollResult::Closed) => StatusCode::NO_CONTENT.into_response(),
+      None => (StatusCode::NOT_FOUND, "No Connection with that ID").into_response(),
+    };
+  }
+
+  let Some(connection) = state.signalr.redeem(&token, user.id) else {
+    return (StatusCode::NOT_FOUND, "No Connection with that ID").into_response();
+  };
+
+  let (socket, transport) = HubSocket::pair();
+
+  if let Ok(ws) = ws {
+    return ws.on_upgrade(move |ws| async move {
+      tokio::spawn(pump_websocket(ws, transport));
+
+      handle_hub(state, hub, socket, connection).await;
+    });
+  }
+
+  let event_stream = headers.get(header::ACCEPT)
+    .and_then(|accept| accept.to_str().ok())
+    .is_some_and(|accept| accept.contains("text/event-stream"));
+
+  if event_stream && hub.protocol() != SignalRProtocol::Json {
+    return (StatusCode::BAD_REQUEST, "Server-sent events aren't supported by this hub").into_response();
+  }
+
+  tokio::spawn(handle_hub(state.clone(), hub, socket, connection));
+
+  if !event_stream {
+    // the first long polling request only establishes the connection
+    state.signalr.activate(token, user.id, Transport::LongPolling, transport);
+
+    return StatusCode::OK.into_response();
+  }
+
+  let Some(outgoing) = state.signalr.activate(token.clone(), user.id, Transport::ServerSentEvents, transport) else {
+    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+  };
+
+  let guard = ConnectionGuard {
+    state: state.clone(),
+    token,
+    user_id: user.id,
+  };
+
+  // server-sent events are text only
+  let stream = UnboundedReceiverStream::new(outgoing)
+    .map(move |data| {
+      let _guard = &guard;
+
+      Ok::<_, Infallible>(Event::default().data(String::from_utf8_lossy(&data)))
+    });
+
+  Sse::new(stream)
+    .keep_alive(KeepAlive::default())
+    .into_response()
+}
+
+async fn signalr_send(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Query(query): Query<HubQuery>,
+  body: Bytes,
+) -> StatusCode {
+  let Some(token) = query.id else {
+    return StatusCode::BAD_REQUEST;
+  };
+
+  match state.signalr.send(&token, user.id, body) {
+    true => StatusCode::OK,
+    false => StatusCode::NOT_FOUND,
+  }
+}
+
+async fn signalr_close(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Query(query): Query<HubQuery>,
+) -> StatusCode {
+  let Some(token) = query.id else {
+    return StatusCode::BAD_REQUEST;
+  };
+
+  match state.signalr.close(&token, user.id) {
+    true => StatusCode::ACCEPTED,
+    false => StatusCode::NOT_FOUND,
   }
 }
 
@@ -22,14 +142,19 @@ struct SignalRTransport {
   transfer_formats: Vec<String>,
 }
 
-impl Default for SignalRTransport {
-  fn default() -> Self {
+impl SignalRTransport {
+  fn new(transport: Transport) -> Self {
+    let (name, transfer_formats) = match transport {
+      Transport::WebSockets => ("WebSockets", vec!["Text", "Binary"]),
+      Transport::ServerSentEvents => ("ServerSentEvents", vec!["Text"]),
+      Transport::LongPolling => ("LongPolling", vec!["Text", "Binary"]),
+    };
+
     Self {
-      transport: "WebSockets".into(),
-      transfer_formats: vec![
-        "Text".into(),
-        "Binary".into(),
-      ],
+      transport: name.into(),
+      transfer_formats: transfer_formats.into_iter()
+        .map(Into::into)
+        .collect(),
     }
   }
 }
@@ -37,32 +162,59 @@ impl Default for SignalRTransport {
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 struct SignalRNegotiate {
-  connection_token: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  connection_token: Option<String>,
   connection_id: String,
   negotiate_version: u8,
   available_transports: Vec<SignalRTransport>,
 }
 
-impl Default for SignalRNegotiate {
-  fn default() -> Self {
-    Self {
-      connection_token: "connectionToken".into(),
-      connection_id: "connectionId".into(),
-      negotiate_version: 1,
-      available_transports: vec![
-        SignalRTransport::default(),
-      ],
-    }
-  }
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NegotiateQuery {
+  #[serde(default)]
+  negotiate_version: u8,
 }
 
-async fn signalr_negotiate() -> Json<SignalRNegotiate> {
-  Json(SignalRNegotiate::default())
+/// highest negotiate protocol version we understand
+const NEGOTIATE_VERSION: u8 = 1;
+
+async fn signalr_negotiate(
+  State(state): State<FiberState>,
+  Extension(user): Extension<User>,
+  Path(hub): Path<String>,
+  Query(query): Query<NegotiateQuery>,
+) -> Result<Json<SignalRNegotiate>, StatusCode> {
+  let Some(hub) = Hub::from_name(&hub) else {
+    return Err(StatusCode::NOT_FOUND);
+  };
+
+  let negotiate_version = query.negotiate_version.min(NEGOTIATE_VERSION);
+
+  let connection = state.signalr.issue(user.id, negotiate_version);
+
+  // server-sent events can only carry text, which the messagepack hubs can't be sent as
+  let mut available_transports = vec![SignalRTransport::new(Transport::WebSockets)];
+
+  if hub.protocol() == SignalRProtocol::Json {
+    available_transports.push(SignalRTransport::new(Transport::ServerSentEvents));
+  }
+
+  available_transports.push(SignalRTransport::new(Transport::LongPolling));
+
+  Ok(Json(SignalRNegotiate {
+    // version 0 clients connect using the connection id
+    connection_token: (negotiate_version > 0).then_some(connection.connection_token),
+    connection_id: connection.connection_id,
+    negotiate_version,
+    available_transports,
+  }))
 }
 
 
-pub fn router() -> Router<FiberState> {
+pub fn router(state: FiberState) -> Router<FiberState> {
   Router::new()
-    .route("/{hub}", get(signalr_hub))
+    .route("/{hub}", get(signalr_hub).post(signalr_send).delete(signalr_close))
     .route("/{hub}/negotiate", post(signalr_negotiate))
+    .layer(middleware::from_fn_with_state((state, "*"), auth::middleware))
 }
\ No newline at end of file
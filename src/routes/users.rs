@@ -1,8 +1,12 @@
-use axum::{extract::{Path, State}, middleware, routing::get, Extension, Json, Router};
-use serde::Serialize;
+use std::collections::HashMap;
+
+use axum::{extract::{Path, Query, State}, http::StatusCode, middleware, response::{IntoResponse, Response}, routing::{get, post}, Extension, Json, Router};
+use axum_typed_multipart::{TryFromMultipart, TypedMultipart};
+use chrono::{NaiveDate, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
 
-use crate::{auth::{self, User}, state::FiberState};
+use crate::{auth::{self, hash_password, Token, User}, config::Config, relations, ruleset::Ruleset, state::FiberState};
 
 #[derive(Serialize)]
 pub struct ApiUser {
@@ -20,10 +24,54 @@ pub struct ApiUser {
   cover_url: String,
   has_supported: bool,
   join_date: String,
+  profile_colour: Option<String>,
+  title: Option<String>,
+  playstyle: Option<Vec<String>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  groups: Option<Vec<ApiGroup>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  rank_history: Option<RankHistory>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  monthly_playcounts: Option<Vec<MonthlyPlaycount>>,
   session_verified: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  session_verification_method: Option<String>,
+  playmode: Ruleset,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  statistics: Option<Statistics>,
   statistics_rulesets: Option<StatisticsRulesets>,
 }
 
+/// days of rank history shown on profiles
+const RANK_HISTORY_DAYS: i64 = 90;
+
+#[derive(Clone, FromRow, Serialize)]
+struct ApiGroup {
+  id: i64,
+  identifier: String,
+  name: String,
+  short_name: String,
+  colour: Option<String>,
+  #[sqlx(default)]
+  has_listing: bool,
+  #[sqlx(default)]
+  has_playmodes: bool,
+  #[sqlx(default)]
+  is_probationary: bool,
+}
+
+#[derive(Serialize)]
+struct RankHistory {
+  mode: Ruleset,
+  data: Vec<u32>,
+}
+
+#[derive(Serialize)]
+struct MonthlyPlaycount {
+  start_date: String,
+  count: i64,
+}
+
 #[derive(Serialize)]
 struct StatisticsRulesets {
   osu: Statistics,
@@ -36,14 +84,14 @@ struct StatisticsRulesets {
 struct Statistics {
   level: StatisticsLevel,
   is_ranked: bool,
-  global_rank: u32,
-  country_rank: u32,
+  global_rank: Option<u32>,
+  country_rank: Option<u32>,
   pp: f32,
-  ranked_score: u32,
-  accuracy: f32,
+  ranked_score: u64,
+  hit_accuracy: f32,
   play_count: u32,
   play_time: u32,
-  total_score: u32,
+  total_score: u64,
   total_hits: u32,
   maximum_combo: u32,
   replays_watched_by_others: u32,
@@ -55,20 +103,20 @@ impl Statistics {
     Self {
       level: StatisticsLevel {
         current: stats.level.floor() as u16,
-        progress: stats.level.fract(),
+        progress: (stats.level.fract() * 100.).floor() as u8,
       },
-      is_ranked: true,
-      global_rank: 1,
-      country_rank: 1,
+      is_ranked: stats.global_rank.is_some(),
+      global_rank: stats.global_rank,
+      country_rank: stats.country_rank,
       pp: stats.pp,
-      ranked_score: 0,
-      accuracy: 0.,
+      ranked_score: stats.ranked_score,
+      hit_accuracy: stats.accuracy * 100.,
       play_count: stats.playcount,
-      play_time: 0,
-      total_score: 0,
-      total_hits: 0,
-      maximum_combo: 0,
-      replays_watched_by_others: 0,
+      play_time: stats.play_time,
+      total_score: stats.total_score,
+      total_hits: stats.total_hits,
+      maximum_combo: stats.maximum_combo,
+      replays_watched_by_others: stats.replays_watched_by_others,
       grade_counts: StatisticsGrades {
         ssh: stats.ssh,
         ss: stats.ss,
@@ -83,7 +131,8 @@ impl Statistics {
 #[derive(Serialize)]
 struct StatisticsLevel {
   current: u16,
-  progress: f32,
+  /// percentage towards the next level
+  progress: u8,
 }
 
 #[derive(Serialize)]
@@ -96,54 +145,127 @@ struct StatisticsGrades {
 }
 
 impl ApiUser {
-  pub fn new(user: &User) -> Self {
+  pub fn new(user: &User, config: &Config) -> Self {
     Self {
-      avatar_url: "https://f.octo.moe/files/ddd854e741d78037.png".into(), // ass pic
-      country_code: "JP".into(),
+      avatar_url: user.avatar_url(config),
+      country_code: user.country_code.clone(),
       id: user.id as u32,
       is_active: true,
-      is_bot: true,
+      is_bot: user.is_bot,
       is_deleted: false,
-      is_online: true,
-      is_supporter: true,
+      is_online: false,
+      is_supporter: user.is_supporter,
       last_visit: None,
-      pm_friends_only: false,
+      pm_friends_only: user.pm_friends_only,
       username: user.username.clone(),
 
-      cover_url: "https://f.octo.moe/files/0c5ea11c11244cc2.jpg".into(),
-      has_supported: true,
+      cover_url: user.cover_url(config),
+      has_supported: user.is_supporter,
       join_date: user.joined_at.to_rfc3339(),
+      profile_colour: user.profile_colour.clone(),
+      title: user.title.clone(),
+      playstyle: serde_json::from_str::<Vec<String>>(&user.playstyle).ok().filter(|playstyle| !playstyle.is_empty()),
+      groups: None,
+      rank_history: None,
+      monthly_playcounts: None,
 
       session_verified: true,
+      session_verification_method: None,
+      playmode: Ruleset::Osu,
+      statistics: None,
       statistics_rulesets: None,
     }
   }
 
-  fn with_statistics(mut self, statistics: StatisticsRulesets) -> Self {
-    self.statistics_rulesets = Some(statistics);
+  /// Adds what's only shown on the full profile: groups, rank history and playcounts
+  fn with_profile(mut self, groups: Vec<ApiGroup>, ranks: Vec<u32>, monthly_playcounts: Vec<MonthlyPlaycount>) -> Self {
+    self.groups = Some(groups);
+    self.rank_history = Some(RankHistory { mode: self.playmode, data: ranks });
+    self.monthly_playcounts = Some(monthly_playcounts);
+
+    self
+  }
+
+  /// Shows whether the user is online, only to viewers that are allowed to see it
+  pub fn with_presence(mut self, state: &FiberState, visible: bool) -> Self {
+    self.is_online = visible && state.online.is_online(self.id as i64);
+
+    self
+  }
+
+  /// Adds the session state of the token the user is authenticated with
+  fn with_session(mut self, token: &Token) -> Self {
+    self.session_verified = token.verified;
+    self.session_verification_method = (!token.verified).then(|| "mail".into());
+
+    self
+  }
+
+  /// Adds statistics for every ruleset, `ruleset` picks the ones shown on the profile
+  fn with_statistics(mut self, ruleset: Ruleset, stats: Vec<DbStatistics>) -> Self {
+    let user_id = self.id as i64;
+
+    let find = |ruleset: Ruleset| {
+      let stats = stats.iter()
+        .find(|s| s.ruleset_id == ruleset.id())
+        .cloned()
+        .unwrap_or_else(|| DbStatistics::new(user_id, ruleset.id()));
+
+      Statistics::new(&stats)
+    };
+
+    self.playmode = ruleset;
+    self.statistics = Some(find(ruleset));
+    self.statistics_rulesets = Some(StatisticsRulesets {
+      osu: find(Ruleset::Osu),
+      taiko: find(Ruleset::Taiko),
+      fruits: find(Ruleset::Fruits),
+      mania: find(Ruleset::Mania),
+    });
 
     self
   }
 }
 
 async fn me(
+  State(state): State<FiberState>,
   Extension(user): Extension<User>,
-) -> Json<ApiUser> {
-  Json(ApiUser::new(&user))
+  Extension(token): Extension<Token>,
+  ruleset: Option<Path<String>>,
+) -> Result<Json<ApiUser>, StatusCode> {
+  let ruleset = ruleset.as_ref().map(|Path(ruleset)| ruleset.as_str());
+
+  let response = user_response(&state, &user, ruleset)
+    .await?
+    .with_session(&token)
+    .with_presence(&state, true);
+
+  Ok(Json(response))
 }
 
 #[derive(Clone, FromRow)]
 struct DbStatistics {
+  #[allow(unused)]
   user_id: i64,
   ruleset_id: u32,
   level: f32,
   pp: f32,
+  accuracy: f32,
   playcount: u32,
+  play_time: u32,
+  ranked_score: u64,
+  total_score: u64,
+  total_hits: u32,
+  maximum_combo: u32,
+  replays_watched_by_others: u32,
   ssh: u32,
   ss: u32,
   sh: u32,
   s: u32,
-  a: u32
+  a: u32,
+  /// only set for users with pp
+  global_rank: Option<u32>,
+  country_rank: Option<u32>,
 }
 
 impl DbStatistics {
@@ -151,72 +273,372 @@ impl DbStatistics {
     Self {
       user_id,
       ruleset_id,
-      level: 0.,
+      level: 1.,
       pp: 0.,
+      accuracy: 0.,
       playcount: 0,
+      play_time: 0,
+      ranked_score: 0,
+      total_score: 0,
+      total_hits: 0,
+      maximum_combo: 0,
+      replays_watched_by_others: 0,
       ssh: 0,
       ss: 0,
       sh: 0,
       s: 0,
       a: 0,
+      global_rank: None,
+      country_rank: None,
     }
   }
 }
 
+async fn fetch_statistics(state: &FiberState, user_id: i64) -> sqlx::Result<Vec<DbStatistics>> {
+  sqlx::query_as::<_, DbStatistics>(r#"
+    select
+      s.*,
+      case when s.pp > 0 then (
+        select count(*) + 1 from statistics o
+        where o.ruleset_id = s.ruleset_id and o.pp > s.pp
+      ) end as global_rank,
+      case when s.pp > 0 then (
+        select count(*) + 1 from statistics o
+        join users ou on ou.id = o.user_id
+        where o.ruleset_id = s.ruleset_id and ou.country_code = u.country_code and o.pp > s.pp
+      ) end as country_rank
+    from statistics s
+    join users u on u.id = s.user_id
+    where s.user_id = ?
+  "#)
+    .bind(user_id)
+    .fetch_all(&state.pool)
+    .await
+}
+
+#[derive(Deserialize)]
+struct UserQuery {
+  key: Option<String>,
+}
+
+/// Finds a user by id, or by username when prefixed with `@` or when `key=username` is passed
+async fn lookup_user(state: &FiberState, lookup: &str, key: Option<&str>) -> sqlx::Result<Option<User>> {
+  let username = match (lookup.strip_prefix('@'), key) {
+    (Some(username), _) => Some(username),
+    (None, Some("username")) => Some(lookup),
+    (None, _) => lookup.parse::<i64>().is_err().then_some(lookup),
+  };
+
+  match username {
+    Some(username) => sqlx::query_as::<_, User>(r#"
+      select * from users
+      where username = ?
+    "#)
+      .bind(username)
+      .fetch_optional(&state.pool)
+      .await,
+    None => sqlx::query_as::<_, User>(r#"
+      select * from users
+      where id = ?
+    "#)
+      .bind(lookup)
+      .fetch_optional(&state.pool)
+      .await,
+  }
+}
+
+async fn user_response(state: &FiberState, user: &User, ruleset: Option<&str>) -> Result<ApiUser, StatusCode> {
+  let ruleset = match ruleset {
+    Some(name) => Ruleset::from_name(name).ok_or(StatusCode::NOT_FOUND)?,
+    None => Ruleset::Osu,
+  };
+
+  let stats = fetch_statistics(state, user.id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+  let groups = fetch_groups(state, user.id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+  let ranks = fetch_rank_history(state, user.id, ruleset)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+  let monthly_playcounts = fetch_monthly_playcounts(state, user.id)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+  Ok(
+    ApiUser::new(user, &state.config)
+      .with_statistics(ruleset, stats)
+      .with_profile(groups, ranks, monthly_playcounts)
+  )
+}
+
+async fn fetch_groups(state: &FiberState, user_id: i64) -> sqlx::Result<Vec<ApiGroup>> {
+  sqlx::query_as::<_, ApiGroup>(r#"
+    select g.* from user_groups ug
+    join groups g on g.id = ug.group_id
+    where ug.user_id = ?
+    order by g.id
+  "#)
+    .bind(user_id)
+    .fetch_all(&state.pool)
+    .await
+}
+
+/// Global rank of every day in the last `RANK_HISTORY_DAYS` the user was ranked on
+/// Global rank on each of the last `RANK_HISTORY_DAYS` days, oldest first.
+///
+/// Lazer draws the ranks as consecutive days, so days without a snapshot or a rank are 0.
+async fn fetch_rank_history(state: &FiberState, user_id: i64, ruleset: Ruleset) -> sqlx::Result<Vec<u32>> {
+  let today = Utc::now().date_naive();
+  let first_day = today - TimeDelta::days(RANK_HISTORY_DAYS - 1);
+
+  let snapshots = sqlx::query_as::<_, (NaiveDate, u32)>(r#"
+    select date, global_rank from statistics_snapshots
+    where user_id = ? and ruleset_id = ? and date >= ? and date <= ? and global_rank is not null
+  "#)
+    .bind(user_id)
+    .bind(ruleset.id())
+    .bind(first_day)
+    .bind(today)
+    .fetch_all(&state.pool)
+    .await?;
+
+  let mut ranks = vec![0; RANK_HISTORY_DAYS as usize];
+
+  for (date, rank) in snapshots {
+    ranks[(date - first_day).num_days() as usize] = rank;
+  }
+
+  Ok(ranks)
+}
+
+/// Plays in each month, from the playcount across all rulesets at the last snapshot of every month
+async fn fetch_monthly_playcounts(state: &FiberState, user_id: i64) -> sqlx::Result<Vec<MonthlyPlaycount>> {
+  let months = sqlx::query_as::<_, (String, i64, i64)>(r#"
+    select strftime('%Y-%m-01', date) as month, min(playcount), max(playcount) from (
+      select date, sum(playcount) as playcount from statistics_snapshots
+      where user_id = ?
+      group by date
+    )
+    group by month
+    order by month
+  "#)
+    .bind(user_id)
+    .fetch_all(&state.pool)
+    .await?;
+
+  // plays from before the first snapshot can't be placed in a month
+  let mut previous = months.first().map_or(0, |(_, first, _)| *first);
+
+  Ok(months.into_iter()
+    .map(|(start_date, _, last)| {
+      let count = last - previous;
+      previous = last;
+
+      MonthlyPlaycount { start_date, count }
+    })
+    .collect())
+}
+
+async fn lookup_user_response(state: &FiberState, lookup: &str, ruleset: Option<&str>, key: Option<&str>, viewer: Option<&User>) -> Result<Json<ApiUser>, StatusCode> {
+  let user = lookup_user(state, lookup, key)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+  let presence_visible = match viewer {
+    Some(viewer) => relations::presence_visible(&state.pool, viewer.id, user.id)
+      .await
+      .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+    None => true,
+  };
+
+  Ok(Json(user_response(state, &user, ruleset).await?.with_presence(state, presence_visible)))
+}
+
 async fn get_user(
   State(state): State<FiberState>,
-  Path(id): Path<i64>,
-) -> Json<ApiUser> {
-  let user = sqlx::query_as::<_, User>(r#"
-    select * from users
-    where id = ?
+  viewer: Option<Extension<User>>,
+  Path(lookup): Path<String>,
+  Query(query): Query<UserQuery>,
+) -> Result<Json<ApiUser>, StatusCode> {
+  let viewer = viewer.map(|Extension(viewer)| viewer);
+
+  lookup_user_response(&state, &lookup, None, query.key.as_deref(), viewer.as_ref()).await
+}
+
+async fn get_user_with_ruleset(
+  State(state): State<FiberState>,
+  viewer: Option<Extension<User>>,
+  Path((lookup, ruleset)): Path<(String, String)>,
+  Query(query): Query<UserQuery>,
+) -> Result<Json<ApiUser>, StatusCode> {
+  let viewer = viewer.map(|Extension(viewer)| viewer);
+
+  lookup_user_response(&state, &lookup, Some(&ruleset), query.key.as_deref(), viewer.as_ref()).await
+}
+
+pub fn validate_username(username: &str) -> Vec<&'static str> {
+  let mut errors = vec![];
+
+  if username.len() < 3 {
+    errors.push("The requested username is too short.");
+  }
+
+  if username.len() > 15 {
+    errors.push("The requested username is too long.");
+  }
+
+  if !username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '[' | ']' | ' ')) {
+    errors.push("The requested username contains invalid characters.");
+  }
+
+  if username.contains('_') && username.contains(' ') {
+    errors.push("Please use either underscores or spaces, not both!");
+  }
+
+  if username.starts_with(' ') || username.ends_with(' ') {
+    errors.push("A username cannot begin or end with spaces!");
+  }
+
+  if username.contains("  ") {
+    errors.push("Please don't use double spaces.");
+  }
+
+  errors
+}
+
+pub fn validate_password(username: &str, password: &str) -> Vec<&'static str> {
+  let mut errors = vec![];
+
+  if password.len() < 8 {
+    errors.push("New password is too short.");
+  }
+
+  if !username.is_empty() && password.to_lowercase().contains(&username.to_lowercase()) {
+    errors.push("Password may not contain username.");
+  }
+
+  errors
+}
+
+fn validate_email(email: &str) -> Vec<&'static str> {
+  let valid = email.split_once('@')
+    .is_some_and(|(local, domain)| !local.is_empty() && domain.contains('.') && !domain.starts_with('.') && !domain.ends_with('.'));
+
+  match valid {
+    true => vec![],
+    false => vec!["Email address is invalid."],
+  }
+}
+
+#[derive(TryFromMultipart)]
+struct RegisterMultipart {
+  #[form_data(field_name = "user[username]")]
+  username: String,
+  #[form_data(field_name = "user[user_email]")]
+  email: String,
+  #[form_data(field_name = "user[password]")]
+  password: String,
+}
+
+/// Registration errors, keyed by form field the same way the client expects them
+#[derive(Default, Serialize)]
+struct RegisterErrors {
+  form_error: RegisterFormError,
+}
+
+#[derive(Default, Serialize)]
+struct RegisterFormError {
+  user: HashMap<&'static str, Vec<&'static str>>,
+}
+
+impl RegisterErrors {
+  fn add(&mut self, field: &'static str, errors: Vec<&'static str>) {
+    if !errors.is_empty() {
+      self.form_error.user.entry(field)
+        .or_default()
+        .extend(errors);
+    }
+  }
+
+  fn is_empty(&self) -> bool {
+    self.form_error.user.is_empty()
+  }
+}
+
+impl IntoResponse for RegisterErrors {
+  fn into_response(self) -> Response {
+    (StatusCode::UNPROCESSABLE_ENTITY, Json(self)).into_response()
+  }
+}
+
+async fn register(
+  State(state): State<FiberState>,
+  body: TypedMultipart<RegisterMultipart>,
+) -> Result<Json<ApiUser>, Response> {
+  let mut errors = RegisterErrors::default();
+
+  errors.add("username", validate_username(&body.username));
+  errors.add("user_email", validate_email(&body.email));
+  errors.add("password", validate_password(&body.username, &body.password));
+
+  let (username_taken, email_taken) = sqlx::query_as::<_, (bool, bool)>(r#"
+    select
+      exists(select 1 from users where username = ?),
+      exists(select 1 from users where email = ?)
   "#)
-    .bind(id)
+    .bind(&body.username)
+    .bind(&body.email)
     .fetch_one(&state.pool)
     .await
-    .unwrap();
-  // let user = sqlx::query_as::<_, User>(r#"
-  //   select * from users
-  //   where id = ?
-  // "#)
-  //   .bind(id)
-  //   .fetch_one(&state.pool)
-  //   .await
-  //   .unwrap();
-
-  // let stats = sqlx::query_as::<_, DbStatistics>(r#"
-  //   select * from statistics
-  //   where user_id = ?
-  // "#)
-  //   .bind(id)
-  //   .fetch_all(&state.pool)
-  //   .await
-  //   .unwrap();
-
-  let stats = StatisticsRulesets {    
-    osu: Statistics::new(&stats.iter().find(|s| s.ruleset_id == 0)
-      .cloned()
-      .unwrap_or_else(|| DbStatistics::new(user.id, 0))),
-    taiko: Statistics::new(&stats.iter().find(|s| s.ruleset_id == 1)
-      .cloned()
-      .unwrap_or_else(|| DbStatistics::new(user.id, 1))),
-    fruits: Statistics::new(&stats.iter().find(|s| s.ruleset_id == 2)
-      .cloned()
-      .unwrap_or_else(|| DbStatistics::new(user.id, 2))),
-    mania: Statistics::new(&stats.iter().find(|s| s.ruleset_id == 3)
-      .cloned()
-      .unwrap_or_else(|| DbStatistics::new(user.id, 3))),
-  };
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  if username_taken {
+    errors.add("username", vec!["This username is already in use!"]);
+  }
+
+  if email_taken {
+    errors.add("user_email", vec!["Email address already used."]);
+  }
 
-  let response = ApiUser::new(&user)
-    .with_statistics(stats);
+  if !errors.is_empty() {
+    return Err(errors.into_response());
+  }
+
+  let hash = hash_password(&body.password)
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+  let user = sqlx::query_as::<_, User>(r#"
+    insert into users (username, email, password_hash, joined_at) values
+    (?, ?, ?, ?)
+    returning *
+  "#)
+    .bind(&body.username)
+    .bind(&body.email)
+    .bind(hash)
+    .bind(Utc::now())
+    .fetch_one(&state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
 
-  Json(response)
+  Ok(Json(ApiUser::new(&user, &state.config)))
 }
 
 pub fn router(state: FiberState) -> Router<FiberState> {
+  let public = Router::new()
+    .route("/api/v2/users/{id}/", get(get_user))
+    .route("/api/v2/users/{id}/{mode}", get(get_user_with_ruleset))
+    .layer(middleware::from_fn_with_state(state.clone(), auth::public_middleware));
+
   Router::new()
     .route("/api/v2/me/", get(me))
-    .route("/api/v2/users/{id}/", get(get_user))
-    .layer(middleware::from_fn_with_state(state, auth::middleware))
+    .route("/api/v2/me/{mode}", get(me))
+    .layer(middleware::from_fn_with_state((state, "identify"), auth::unverified_middleware))
+    .merge(public)
+    .route("/users", post(register))
 }
\ No newline at end of file
@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Ruleset {
+  Osu,
+  Taiko,
+  Fruits,
+  Mania,
+}
+
+impl Ruleset {
+  pub const ALL: [Ruleset; 4] = [Self::Osu, Self::Taiko, Self::Fruits, Self::Mania];
+
+  pub fn from_id(id: u32) -> Option<Self> {
+    Self::ALL.get(id as usize).copied()
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    Self::ALL.into_iter()
+      .find(|ruleset| ruleset.name() == name)
+  }
+
+  pub fn id(self) -> u32 {
+    self as u32
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::Osu => "osu",
+      Self::Taiko => "taiko",
+      Self::Fruits => "fruits",
+      Self::Mania => "mania",
+    }
+  }
+}
\ No newline at end of file
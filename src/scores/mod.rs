@@ -0,0 +1,3 @@
+pub mod processing;
+pub mod replay;
+pub mod statistics;
\ No newline at end of file
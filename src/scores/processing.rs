@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+
+use rosu_mods::GameMod;
+use serde_json::Value;
+
+use crate::{beatmaps::difficulty::mods_mode, ruleset::Ruleset};
+
+/// Groups of hit results that are judged against the same maximum result
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum ResultKind {
+  /// hit circles, notes, fruits, the object itself
+  Basic,
+  /// slider ticks and repeats, droplets
+  LargeTick,
+  SliderTail,
+  /// tiny droplets
+  SmallTick,
+  Bonus,
+  Ignored,
+}
+
+/// Kind and base score of a hit result, as in lazer's `Judgement.ToNumericResult`
+fn hit_result(ruleset: Ruleset, result: &str) -> Option<(ResultKind, f64)> {
+  Some(match result {
+    "miss" => (ResultKind::Basic, 0.),
+    "meh" => (ResultKind::Basic, 50.),
+    "ok" => (ResultKind::Basic, 100.),
+    "good" => (ResultKind::Basic, 200.),
+    "great" => (ResultKind::Basic, 300.),
+    "perfect" if ruleset == Ruleset::Mania => (ResultKind::Basic, 305.),
+    "perfect" => (ResultKind::Basic, 315.),
+    "large_tick_miss" => (ResultKind::LargeTick, 0.),
+    "large_tick_hit" => (ResultKind::LargeTick, 30.),
+    "slider_tail_hit" => (ResultKind::SliderTail, 150.),
+    "small_tick_miss" => (ResultKind::SmallTick, 0.),
+    "small_tick_hit" => (ResultKind::SmallTick, 10.),
+    "small_bonus" => (ResultKind::Bonus, 10.),
+    "large_bonus" => (ResultKind::Bonus, 50.),
+    "none" | "ignore_miss" | "ignore_hit" | "combo_break" | "legacy_combo_increase" => (ResultKind::Ignored, 0.),
+    _ => return None,
+  })
+}
+
+/// Score multiplier of a mod at its default settings.
+///
+/// These are the `ScoreMultiplier` overrides of the mods in lazer's `osu.Game.Rulesets.{Osu,Taiko,Catch,Mania}/Mods`,
+/// every other mod keeps `Mod.ScoreMultiplier`'s default of 1.
+fn mod_multiplier(ruleset: Ruleset, acronym: &str) -> f64 {
+  match (ruleset, acronym) {
+    (_, "EZ" | "NF") => 0.5,
+    (Ruleset::Mania, "HT" | "DC") => 0.5,
+    (_, "HT" | "DC") => 0.3,
+    (Ruleset::Osu, "HR") => 1.1,
+    (Ruleset::Taiko, "HR") => 1.06,
+    (Ruleset::Fruits, "HR") => 1.12,
+    (Ruleset::Osu, "DT" | "NC") => 1.2,
+    (Ruleset::Taiko | Ruleset::Fruits, "DT" | "NC") => 1.12,
+    (Ruleset::Osu | Ruleset::Taiko | Ruleset::Fruits, "HD") => 1.06,
+    (Ruleset::Osu | Ruleset::Taiko | Ruleset::Fruits, "FL") => 1.12,
+    (Ruleset::Osu, "SO") => 0.9,
+    (_, "DA") => 0.5,
+    (_, "RX" | "AP" | "TP") => 0.1,
+    (_, "CL") => 0.96,
+    (Ruleset::Mania, "1K" | "2K" | "3K" | "4K" | "5K" | "6K" | "7K" | "8K" | "9K" | "10K") => 0.9,
+    _ => 1.,
+  }
+}
+
+/// What the server knows of the beatmap a play was on, in the play's ruleset
+#[derive(Default)]
+pub struct BeatmapLimits {
+  /// the number of objects judged with a basic result
+  pub objects: Option<u32>,
+  pub max_combo: Option<u32>,
+}
+
+/// What the server can compute of a play from its hit statistics
+pub struct ProcessedScore {
+  /// the most the play could be worth with its accuracy, if every combo reached `max_combo`
+  pub max_total_score: i64,
+  pub accuracy: f64,
+  pub rank: &'static str,
+}
+
+fn acronyms(mods: &[Value]) -> impl Iterator<Item = &str> {
+  mods.iter().filter_map(|m| m.get("acronym").and_then(Value::as_str))
+}
+
+/// Whether every mod exists in `ruleset`
+pub fn valid_mods(mods: &[Value], ruleset: Ruleset) -> bool {
+  acronyms(mods).count() == mods.len()
+    && acronyms(mods).all(|acronym| !matches!(
+      GameMod::new(acronym, mods_mode(ruleset)),
+      GameMod::UnknownOsu(_) | GameMod::UnknownTaiko(_) | GameMod::UnknownCatch(_) | GameMod::UnknownMania(_),
+    ))
+}
+
+/// Sum of `sqrt(k)` over a combo building up from 1 to `combo`, lazer weighs each hit by the square root of the combo.
+///
+/// Approximated in closed form so huge submitted combos can't make it expensive.
+fn combo_sum(combo: u64) -> f64 {
+  if combo == 0 {
+    return 0.;
+  }
+
+  let n = combo as f64;
+
+  2. / 3. * n.powf(1.5) + 0.5 * n.sqrt() - 0.207_886_224_977_354_9
+}
+
+fn rank(ruleset: Ruleset, accuracy: f64, misses: u32, mods: &[Value]) -> &'static str {
+  let cutoffs = match ruleset {
+    Ruleset::Fruits => [0.98, 0.94, 0.9, 0.85],
+    _ => [0.95, 0.9, 0.8, 0.7],
+  };
+
+  let rank = match accuracy {
+    a if a >= 1. => "X",
+    a if a >= cutoffs[0] => "S",
+    a if a >= cutoffs[1] => "A",
+    a if a >= cutoffs[2] => "B",
+    a if a >= cutoffs[3] => "C",
+    _ => "D",
+  };
+
+  // osu! and taiko don't give an S to plays with misses
+  let rank = match (ruleset, rank) {
+    (Ruleset::Osu | Ruleset::Taiko, "X" | "S") if misses > 0 => "A",
+    _ => rank,
+  };
+
+  let silver = acronyms(mods).any(|acronym| matches!(acronym, "HD" | "FL" | "FI"));
+
+  match (rank, silver) {
+    ("X", true) => "XH",
+    ("S", true) => "SH",
+    _ => rank,
+  }
+}
+
+/// Recomputes a play's accuracy and rank from its hit statistics with lazer's standardised scoring.
+///
+/// `limits` bound the number of objects judged and the combo on the map when they're known.
+/// The order of the judgements isn't submitted, so the combo part of the total score can't be recomputed exactly,
+/// only the most the play could be worth is, with every combo reaching `max_combo`.
+pub fn process(
+  ruleset: Ruleset,
+  passed: bool,
+  mods: &[Value],
+  max_combo: u32,
+  statistics: &HashMap<String, u32>,
+  maximum_statistics: &HashMap<String, u32>,
+  limits: BeatmapLimits,
+) -> Result<ProcessedScore, &'static str> {
+  if !valid_mods(mods, ruleset) {
+    return Err("invalid mods");
+  }
+
+  let mut judged = HashMap::<ResultKind, (u64, f64)>::new();
+
+  for (result, count) in statistics {
+    let Some((kind, score)) = hit_result(ruleset, result) else {
+      return Err("invalid statistics");
+    };
+
+    let entry = judged.entry(kind).or_default();
+    entry.0 += *count as u64;
+    entry.1 += *count as f64 * score;
+  }
+
+  let mut maximum = HashMap::<ResultKind, (u64, f64)>::new();
+
+  for (result, count) in maximum_statistics {
+    let Some((kind, score)) = hit_result(ruleset, result) else {
+      return Err("invalid maximum_statistics");
+    };
+
+    let entry = maximum.entry(kind).or_default();
+    entry.0 += *count as u64;
+    entry.1 += *count as f64 * score;
+  }
+
+  let count = |results: &HashMap<ResultKind, (u64, f64)>, kind| results.get(&kind).map_or(0, |(count, _)| *count);
+  let score = |results: &HashMap<ResultKind, (u64, f64)>, kind| results.get(&kind).map_or(0., |(_, score)| *score);
+
+  // slider tails are judged as large tick misses when they're missed
+  let ticks = |results: &HashMap<ResultKind, (u64, f64)>| count(results, ResultKind::LargeTick) + count(results, ResultKind::SliderTail);
+
+  if count(&judged, ResultKind::Basic) > count(&maximum, ResultKind::Basic)
+    || ticks(&judged) > ticks(&maximum)
+    || count(&judged, ResultKind::SmallTick) > count(&maximum, ResultKind::SmallTick)
+  {
+    return Err("more hits than objects");
+  }
+
+  if passed && count(&judged, ResultKind::Basic) != count(&maximum, ResultKind::Basic) {
+    return Err("passed scores must judge every object");
+  }
+
+  if limits.objects.is_some_and(|objects| count(&maximum, ResultKind::Basic) > objects as u64) {
+    return Err("more hits than objects");
+  }
+
+  let combo_objects = count(&maximum, ResultKind::Basic) + ticks(&maximum);
+  let beatmap_max_combo = limits.max_combo.map_or(combo_objects, |combo| combo as u64);
+
+  if max_combo as u64 > beatmap_max_combo {
+    return Err("max_combo is higher than the beatmap's");
+  }
+
+  let accuracy_kinds = [ResultKind::Basic, ResultKind::LargeTick, ResultKind::SliderTail, ResultKind::SmallTick];
+
+  let total = |results: &HashMap<ResultKind, (u64, f64)>| accuracy_kinds.iter()
+    .map(|kind| (count(results, *kind), score(results, *kind)))
+    .fold((0, 0.), |(count, score), (c, s)| (count + c, score + s));
+
+  let (judged_count, judged_score) = total(&judged);
+  let (maximum_count, maximum_score) = total(&maximum);
+
+  // accuracy is relative to what was judged so far, a failed play is only compared to the objects it reached
+  let judged_maximum_score = if passed {
+    maximum_score
+  } else {
+    accuracy_kinds.iter()
+      .filter(|kind| count(&maximum, **kind) > 0)
+      .map(|kind| count(&judged, *kind) as f64 * score(&maximum, *kind) / count(&maximum, *kind) as f64)
+      .sum()
+  };
+
+  let accuracy = if judged_maximum_score > 0. { (judged_score / judged_maximum_score).min(1.) } else { 1. };
+  let accuracy_progress = if maximum_count > 0 { judged_count as f64 / maximum_count as f64 } else { 1. };
+
+  let combo_hits = ["meh", "ok", "good", "great", "perfect", "large_tick_hit", "slider_tail_hit"].iter()
+    .filter_map(|result| statistics.get(*result))
+    .map(|count| *count as u64)
+    .sum::<u64>();
+
+  let average = |score: f64, count: u64| if count > 0 { score / count as f64 } else { 0. };
+
+  let combo_score = score(&judged, ResultKind::Basic) + score(&judged, ResultKind::LargeTick) + score(&judged, ResultKind::SliderTail);
+  let maximum_combo_score = score(&maximum, ResultKind::Basic) + score(&maximum, ResultKind::LargeTick) + score(&maximum, ResultKind::SliderTail);
+
+  // the best order packs the hits into as many combos of `max_combo` as fit
+  let combo_portion = match max_combo as u64 {
+    0 => 0.,
+    combo => average(combo_score, combo_hits) * (
+      (combo_hits / combo) as f64 * combo_sum(combo) + combo_sum(combo_hits % combo)
+    ),
+  };
+
+  let maximum_combo_portion = average(maximum_combo_score, combo_objects) * combo_sum(beatmap_max_combo);
+  let combo_progress = if maximum_combo_portion > 0. { (combo_portion / maximum_combo_portion).min(1.) } else { 1. };
+
+  let bonus = score(&judged, ResultKind::Bonus);
+
+  let max_total_score = match ruleset {
+    Ruleset::Osu => 700_000. * combo_progress + 300_000. * accuracy.powi(10) * accuracy_progress,
+    Ruleset::Taiko => 250_000. * combo_progress + 750_000. * accuracy.powf(3.6) * accuracy_progress,
+    Ruleset::Fruits => 600_000. * combo_progress + 400_000. * accuracy * accuracy_progress,
+    Ruleset::Mania => 150_000. * combo_progress + 850_000. * accuracy.powf(2. + 2. * accuracy) * accuracy_progress,
+  } + bonus;
+
+  let multiplier = acronyms(mods)
+    .map(|acronym| mod_multiplier(ruleset, acronym))
+    .product::<f64>();
+
+  let misses = statistics.get("miss").copied().unwrap_or_default();
+
+  Ok(ProcessedScore {
+    max_total_score: (max_total_score * multiplier).round() as i64,
+    accuracy,
+    rank: if passed { rank(ruleset, accuracy, misses, mods) } else { "F" },
+  })
+}
\ No newline at end of file
@@ -0,0 +1,211 @@
+use std::{error::Error, fmt::Display, path::PathBuf};
+
+use anyhow::Result;
+use tokio::fs;
+
+#[derive(Debug)]
+pub enum ReplayError {
+  UnexpectedEnd,
+  InvalidString,
+  InvalidLength,
+}
+
+impl Display for ReplayError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:?}", self)
+  }
+}
+
+impl Error for ReplayError {}
+
+/// The part of an `.osr` file in front of the replay frames
+#[derive(Debug, Clone)]
+pub struct ReplayHeader {
+  pub ruleset_id: u8,
+  pub version: i32,
+  pub beatmap_hash: String,
+  pub username: String,
+  pub max_combo: u16,
+}
+
+struct Reader<'a> {
+  data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, len: usize) -> Result<&'a [u8], ReplayError> {
+    if self.data.len() < len {
+      return Err(ReplayError::UnexpectedEnd);
+    }
+
+    let (taken, rest) = self.data.split_at(len);
+    self.data = rest;
+
+    Ok(taken)
+  }
+
+  fn u8(&mut self) -> Result<u8, ReplayError> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn u16(&mut self) -> Result<u16, ReplayError> {
+    Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+  }
+
+  fn i32(&mut self) -> Result<i32, ReplayError> {
+    Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+  }
+
+  fn uleb128(&mut self) -> Result<usize, ReplayError> {
+    let mut value = 0usize;
+
+    for shift in (0..64).step_by(7) {
+      let byte = self.u8()?;
+      value |= ((byte & 0x7f) as usize) << shift;
+
+      if byte & 0x80 == 0 {
+        return Ok(value);
+      }
+    }
+
+    Err(ReplayError::InvalidString)
+  }
+
+  /// Strings are either a lone `0x00`, or `0x0b` followed by their uleb128 length
+  fn string(&mut self) -> Result<String, ReplayError> {
+    match self.u8()? {
+      0x00 => Ok(String::new()),
+      0x0b => {
+        let len = self.uleb128()?;
+
+        String::from_utf8(self.take(len)?.to_vec())
+          .map_err(|_| ReplayError::InvalidString)
+      },
+      _ => Err(ReplayError::InvalidString),
+    }
+  }
+}
+
+impl ReplayHeader {
+  /// Reads the header of an `.osr` file, making sure the compressed frames it announces are actually there
+  pub fn parse(data: &[u8]) -> Result<Self, ReplayError> {
+    let mut reader = Reader { data };
+
+    let ruleset_id = reader.u8()?;
+    let version = reader.i32()?;
+    let beatmap_hash = reader.string()?;
+    let username = reader.string()?;
+    let _replay_hash = reader.string()?;
+
+    // hit counts and total score
+    reader.take(6 * 2 + 4)?;
+
+    let max_combo = reader.u16()?;
+
+    // perfect, mods, life bar and timestamp
+    reader.take(1 + 4)?;
+    reader.string()?;
+    reader.take(8)?;
+
+    let Ok(frames) = usize::try_from(reader.i32()?) else {
+      return Err(ReplayError::InvalidLength);
+    };
+
+    reader.take(frames)?;
+
+    Ok(Self { ruleset_id, version, beatmap_hash, username, max_combo })
+  }
+}
+
+/// Replays on disk, stored under the id of their score
+pub struct ReplayStore {
+  root: PathBuf,
+}
+
+impl ReplayStore {
+  pub fn new(root: PathBuf) -> Self {
+    Self { root }
+  }
+
+  fn path(&self, score_id: i64) -> PathBuf {
+    self.root.join(format!("{}.osr", score_id))
+  }
+
+  pub async fn put(&self, score_id: i64, data: &[u8]) -> Result<()> {
+    let path = self.path(score_id);
+
+    fs::create_dir_all(&self.root).await?;
+
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data).await?;
+    fs::rename(&tmp, &path).await?;
+
+    Ok(())
+  }
+
+  pub async fn get(&self, score_id: i64) -> Result<Vec<u8>> {
+    Ok(fs::read(self.path(score_id)).await?)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn string(value: &str) -> Vec<u8> {
+    let mut data = vec![0x0b, value.len() as u8];
+    data.extend_from_slice(value.as_bytes());
+
+    data
+  }
+
+  fn header(frames: i32, frame_data: &[u8]) -> Vec<u8> {
+    let mut data = vec![0];
+    data.extend_from_slice(&20250101i32.to_le_bytes());
+    data.extend(string("0123456789abcdef0123456789abcdef"));
+    data.extend(string("peppy"));
+    data.push(0x00);
+    data.extend_from_slice(&[0; 6 * 2 + 4]);
+    data.extend_from_slice(&727u16.to_le_bytes());
+    data.extend_from_slice(&[0; 1 + 4]);
+    data.push(0x00);
+    data.extend_from_slice(&[0; 8]);
+    data.extend_from_slice(&frames.to_le_bytes());
+    data.extend_from_slice(frame_data);
+
+    data
+  }
+
+  #[test]
+  fn parses_valid_header() {
+    let header = ReplayHeader::parse(&header(3, &[1, 2, 3])).unwrap();
+
+    assert_eq!(header.ruleset_id, 0);
+    assert_eq!(header.version, 20250101);
+    assert_eq!(header.beatmap_hash, "0123456789abcdef0123456789abcdef");
+    assert_eq!(header.username, "peppy");
+    assert_eq!(header.max_combo, 727);
+  }
+
+  #[test]
+  fn rejects_truncated_input() {
+    let data = header(3, &[1, 2, 3]);
+
+    assert!(matches!(ReplayHeader::parse(&data[..data.len() - 1]), Err(ReplayError::UnexpectedEnd)));
+    assert!(matches!(ReplayHeader::parse(&data[..10]), Err(ReplayError::UnexpectedEnd)));
+    assert!(matches!(ReplayHeader::parse(&[]), Err(ReplayError::UnexpectedEnd)));
+  }
+
+  #[test]
+  fn rejects_bad_string_marker() {
+    let mut data = header(0, &[]);
+    data[5] = 0x0c;
+
+    assert!(matches!(ReplayHeader::parse(&data), Err(ReplayError::InvalidString)));
+  }
+
+  #[test]
+  fn rejects_negative_length() {
+    assert!(matches!(ReplayHeader::parse(&header(-1, &[])), Err(ReplayError::InvalidLength)));
+  }
+}
\ No newline at end of file
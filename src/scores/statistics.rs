@@ -0,0 +1,245 @@
+use std::{collections::HashMap, time::Duration};
+
+use chrono::Utc;
+use sqlx::{prelude::FromRow, SqlitePool};
+
+/// hit results that count towards a user's total hits
+const HIT_RESULTS: [&str; 5] = ["perfect", "great", "good", "ok", "meh"];
+
+/// how many of the best scores are weighted into a user's accuracy and pp
+const WEIGHTED_SCORES: usize = 100;
+
+/// how many ranked maps a user needs to have played to get the whole bonus pp
+const BONUS_PP_SCORES: i32 = 1000;
+
+/// Total score needed to reach `level`
+fn score_for_level(level: u32) -> f64 {
+  let n = level as f64;
+
+  if level <= 100 {
+    5000. / 3. * (4. * n.powi(3) - 3. * n.powi(2) - n) + 1.25 * 1.8f64.powf(n - 60.)
+  } else {
+    26_931_190_827. + 99_999_999_999. * (n - 100.)
+  }
+}
+
+/// Level reached with `total_score`, the fractional part is the progress towards the next one
+pub fn level(total_score: u64) -> f64 {
+  let score = total_score as f64;
+
+  if score >= score_for_level(100) {
+    return 100. + (score - score_for_level(100)) / 99_999_999_999.;
+  }
+
+  let mut level = 1;
+
+  while score_for_level(level + 1) <= score {
+    level += 1;
+  }
+
+  let current = score_for_level(level);
+  let next = score_for_level(level + 1);
+
+  level as f64 + ((score - current) / (next - current)).max(0.)
+}
+
+/// Weights the values of sorted scores, each one counting 95% as much as the one before it
+pub fn weighted<T>(values: impl Iterator<Item = T>, value: impl Fn(T) -> f64) -> (f64, f64) {
+  values.take(WEIGHTED_SCORES)
+    .enumerate()
+    .fold((0., 0.), |(sum, weights), (i, item)| {
+      let weight = 0.95f64.powi(i as i32);
+
+      (sum + value(item) * weight, weights + weight)
+    })
+}
+
+#[derive(FromRow)]
+struct BestScore {
+  rank: String,
+  total_score: i64,
+  accuracy: f64,
+}
+
+/// Adds a play to the user's statistics, then recalculates everything that depends on their best scores
+pub async fn record_play(
+  pool: &SqlitePool,
+  user_id: i64,
+  ruleset_id: u32,
+  total_score: i64,
+  statistics: &HashMap<String, u32>,
+  play_time: i64,
+) -> sqlx::Result<()> {
+  let total_hits = HIT_RESULTS.iter()
+    .filter_map(|result| statistics.get(*result))
+    .sum::<u32>();
+
+  sqlx::query(r#"
+    insert into statistics (user_id, ruleset_id, playcount, play_time, total_score, total_hits)
+    values (?, ?, 1, ?, ?, ?)
+    on conflict (user_id, ruleset_id) do update set
+      playcount = playcount + 1,
+      play_time = play_time + excluded.play_time,
+      total_score = total_score + excluded.total_score,
+      total_hits = total_hits + excluded.total_hits
+  "#)
+    .bind(user_id)
+    .bind(ruleset_id)
+    .bind(play_time)
+    .bind(total_score)
+    .bind(total_hits)
+    .execute(pool)
+    .await?;
+
+  recalculate(pool, user_id, ruleset_id).await
+}
+
+/// Extra pp for having set many scores, approaching 416.67
+fn bonus_pp(scores: usize) -> f64 {
+  416.6667 * (1. - 0.995f64.powi((scores as i32).min(BONUS_PP_SCORES)))
+}
+
+/// Recalculates ranked score, grade counts, accuracy, pp, max combo and level from the user's scores
+pub async fn recalculate(pool: &SqlitePool, user_id: i64, ruleset_id: u32) -> sqlx::Result<()> {
+  // only the best pass on each ranked, approved or loved map counts
+  let best = sqlx::query_as::<_, BestScore>(r#"
+    select rank, total_score, accuracy from (
+      select
+        sc.*,
+        row_number() over (partition by sc.beatmap_id order by sc.total_score desc, sc.id) as n
+      from scores sc
+      join beatmaps b on b.id = sc.beatmap_id
+      where sc.user_id = ? and sc.ruleset_id = ? and sc.passed and b.status in (1, 2, 4)
+    )
+    where n = 1
+    order by pp desc nulls last, total_score desc
+  "#)
+    .bind(user_id)
+    .bind(ruleset_id)
+    .fetch_all(pool)
+    .await?;
+
+  let mut grades = HashMap::<&str, u32>::new();
+
+  for score in &best {
+    let grade = match score.rank.as_str() {
+      "XH" => "ssh",
+      "X" => "ss",
+      "SH" => "sh",
+      "S" => "s",
+      "A" => "a",
+      _ => continue,
+    };
+
+    *grades.entry(grade).or_default() += 1;
+  }
+
+  let ranked_score = best.iter()
+    .map(|score| score.total_score)
+    .sum::<i64>();
+
+  let (accuracy, weights) = weighted(best.iter(), |score| score.accuracy);
+  let accuracy = if weights > 0. { accuracy / weights } else { 0. };
+
+  // loved maps don't award pp, and the best play by pp counts even if another one has more score
+  let best_pp = sqlx::query_scalar::<_, f64>(r#"
+    select max(sc.pp) as pp
+    from scores sc
+    join beatmaps b on b.id = sc.beatmap_id
+    where sc.user_id = ? and sc.ruleset_id = ? and sc.passed and sc.pp is not null and b.status in (1, 2)
+    group by sc.beatmap_id
+    order by pp desc
+  "#)
+    .bind(user_id)
+    .bind(ruleset_id)
+    .fetch_all(pool)
+    .await?;
+
+  let (pp, _) = weighted(best_pp.iter(), |pp| *pp);
+  let pp = pp + bonus_pp(best_pp.len());
+
+  let (total_score, maximum_combo) = sqlx::query_as::<_, (i64, i64)>(r#"
+    select
+      st.total_score,
+      (select coalesce(max(max_combo), 0) from scores where user_id = st.user_id and ruleset_id = st.ruleset_id)
+    from statistics st
+    where st.user_id = ? and st.ruleset_id = ?
+  "#)
+    .bind(user_id)
+    .bind(ruleset_id)
+    .fetch_optional(pool)
+    .await?
+    .unwrap_or_default();
+
+  let grade = |name: &str| grades.get(name).copied().unwrap_or_default();
+
+  sqlx::query(r#"
+    update statistics
+    set
+      level = ?,
+      accuracy = ?,
+      pp = ?,
+      ranked_score = ?,
+      maximum_combo = ?,
+      ssh = ?,
+      ss = ?,
+      sh = ?,
+      s = ?,
+      a = ?
+    where user_id = ? and ruleset_id = ?
+  "#)
+    .bind(level(total_score.max(0) as u64))
+    .bind(accuracy)
+    .bind(pp)
+    .bind(ranked_score)
+    .bind(maximum_combo)
+    .bind(grade("ssh"))
+    .bind(grade("ss"))
+    .bind(grade("sh"))
+    .bind(grade("s"))
+    .bind(grade("a"))
+    .bind(user_id)
+    .bind(ruleset_id)
+    .execute(pool)
+    .await?;
+
+  Ok(())
+}
+
+/// Records today's pp, rank and playcount of every user, replacing the snapshot already taken today
+pub async fn snapshot(pool: &SqlitePool) -> sqlx::Result<()> {
+  sqlx::query(r#"
+    insert into statistics_snapshots (user_id, ruleset_id, date, pp, global_rank, playcount)
+    select
+      user_id,
+      ruleset_id,
+      ?,
+      pp,
+      case when pp > 0 then rank() over (partition by ruleset_id order by pp desc) end,
+      playcount
+    from statistics
+    where true
+    on conflict (user_id, ruleset_id, date) do update set
+      pp = excluded.pp,
+      global_rank = excluded.global_rank,
+      playcount = excluded.playcount
+  "#)
+    .bind(Utc::now().date_naive())
+    .execute(pool)
+    .await?;
+
+  Ok(())
+}
+
+/// Takes a snapshot every hour, so each day ends up with the statistics from its last hour
+pub async fn take_snapshots(pool: SqlitePool) {
+  let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+
+  loop {
+    interval.tick().await;
+
+    if let Err(e) = snapshot(&pool).await {
+      eprintln!("failed to snapshot statistics: {:?}", e);
+    }
+  }
+}
\ No newline at end of file
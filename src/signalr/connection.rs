@@ -0,0 +1,214 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use axum::body::Bytes;
+use tokio::sync::{mpsc::{error::TryRecvError, UnboundedReceiver, UnboundedSender}, Mutex as AsyncMutex};
+use uuid::Uuid;
+
+use super::transport::{Transport, TransportSocket};
+
+/// how long a negotiated connection token can sit unused before it's rejected
+const TOKEN_LIFETIME: Duration = Duration::from_secs(60);
+
+/// how long a long polling request is held open when there's nothing to send
+const POLL_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// how long a long polling client can go without polling before it's considered gone
+const POLL_DISCONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct PendingConnection {
+  connection_id: String,
+  user_id: i64,
+  issued_at: Instant,
+}
+
+/// Connection issued by negotiate and redeemed by the transport
+#[derive(Debug)]
+pub struct SignalRConnection {
+  pub id: String,
+  pub user_id: i64,
+}
+
+pub struct IssuedConnection {
+  pub connection_id: String,
+  pub connection_token: String,
+}
+
+/// Connection running over a transport that isn't a websocket,
+/// where every client request is a separate http request
+struct ActiveConnection {
+  user_id: i64,
+  incoming: UnboundedSender<Bytes>,
+  /// only present for long polling, server-sent events stream it directly
+  outgoing: Option<Arc<AsyncMutex<UnboundedReceiver<Bytes>>>>,
+  last_poll: Instant,
+}
+
+pub enum PollResult {
+  Data(Bytes),
+  Timeout,
+  Closed,
+}
+
+#[derive(Default)]
+pub struct SignalRConnections {
+  pending: Mutex<HashMap<String, PendingConnection>>,
+  active: Mutex<HashMap<String, ActiveConnection>>,
+}
+
+fn generate_id() -> String {
+  Uuid::new_v4().simple().to_string()
+}
+
+impl SignalRConnections {
+  /// Drops expired connection tokens and long polling clients that stopped polling,
+  /// since neither of them tells us they're gone
+  fn sweep(&self) {
+    self.pending.lock().unwrap()
+      .retain(|_, connection| connection.issued_at.elapsed() < TOKEN_LIFETIME);
+
+    self.active.lock().unwrap().retain(|_, connection| {
+      connection.outgoing.as_ref().is_none_or(|outgoing| {
+        outgoing.try_lock().is_err() || connection.last_poll.elapsed() < POLL_DISCONNECT_TIMEOUT
+      })
+    });
+  }
+
+  /// Issues a new connection for `user_id`.
+  ///
+  /// With negotiateVersion 0 the client connects using the connection id itself,
+  /// starting from 1 it uses a separate (secret) connection token.
+  pub fn issue(&self, user_id: i64, negotiate_version: u8) -> IssuedConnection {
+    let connection_id = generate_id();
+    let connection_token = match negotiate_version {
+      0 => connection_id.clone(),
+      _ => generate_id(),
+    };
+
+    self.sweep();
+
+    self.pending.lock().unwrap().insert(connection_token.clone(), PendingConnection {
+      connection_id: connection_id.clone(),
+      user_id,
+      issued_at: Instant::now(),
+    });
+
+    IssuedConnection {
+      connection_id,
+      connection_token,
+    }
+  }
+
+  /// Consumes a connection token, returning the connection if it was issued
+  /// to `user_id` and hasn't expired yet.
+  pub fn redeem(&self, token: &str, user_id: i64) -> Option<SignalRConnection> {
+    let mut pending = self.pending.lock().unwrap();
+
+    if pending.get(token)?.user_id != user_id {
+      return None;
+    }
+
+    let connection = pending.remove(token)?;
+
+    if connection.issued_at.elapsed() >= TOKEN_LIFETIME {
+      return None;
+    }
+
+    Some(SignalRConnection {
+      id: connection.connection_id,
+      user_id: connection.user_id,
+    })
+  }
+
+  /// Registers a server-sent events or long polling connection under its token.
+  ///
+  /// Returns the receiving end of the outgoing channel for server-sent events,
+  /// long polling reads it through [`SignalRConnections::poll`] instead.
+  pub fn activate(&self, token: String, user_id: i64, transport: Transport, socket: TransportSocket) -> Option<UnboundedReceiver<Bytes>> {
+    self.sweep();
+
+    let (outgoing, rx) = match transport {
+      Transport::LongPolling => (Some(Arc::new(AsyncMutex::new(socket.outgoing))), None),
+      _ => (None, Some(socket.outgoing)),
+    };
+
+    self.active.lock().unwrap().insert(token, ActiveConnection {
+      user_id,
+      incoming: socket.incoming,
+      outgoing,
+      last_poll: Instant::now(),
+    });
+
+    rx
+  }
+
+  pub fn is_active(&self, token: &str, user_id: i64) -> bool {
+    self.active.lock().unwrap()
+      .get(token)
+      .is_some_and(|connection| connection.user_id == user_id)
+  }
+
+  /// Forwards a payload sent by the client to its hub
+  pub fn send(&self, token: &str, user_id: i64, data: Bytes) -> bool {
+    let active = self.active.lock().unwrap();
+
+    let Some(connection) = active.get(token) else {
+      return false
+    };
+
+    connection.user_id == user_id && connection.incoming.send(data).is_ok()
+  }
+
+  /// Waits for the hub to send something to a long polling client
+  pub async fn poll(&self, token: &str, user_id: i64) -> Option<PollResult> {
+    let outgoing = {
+      let active = self.active.lock().unwrap();
+
+      let connection = active.get(token)
+        .filter(|connection| connection.user_id == user_id)?;
+
+      connection.outgoing.clone()?
+    };
+
+    let mut outgoing = outgoing.lock().await;
+
+    let result = match tokio::time::timeout(POLL_TIMEOUT, outgoing.recv()).await {
+      Ok(Some(data)) => {
+        let mut buf = data.to_vec();
+
+        loop {
+          match outgoing.try_recv() {
+            Ok(data) => buf.extend_from_slice(&data),
+            Err(TryRecvError::Empty | TryRecvError::Disconnected) => break PollResult::Data(buf.into()),
+          }
+        }
+      },
+      Ok(None) => PollResult::Closed,
+      Err(_) => PollResult::Timeout,
+    };
+
+    drop(outgoing);
+
+    let mut active = self.active.lock().unwrap();
+
+    if let PollResult::Closed = result {
+      active.remove(token);
+    } else if let Some(connection) = active.get_mut(token) {
+      connection.last_poll = Instant::now();
+    }
+
+    Some(result)
+  }
+
+  /// Drops a connection, which ends its hub
+  pub fn close(&self, token: &str, user_id: i64) -> bool {
+    let mut active = self.active.lock().unwrap();
+
+    if active.get(token).is_none_or(|connection| connection.user_id != user_id) {
+      return false;
+    }
+
+    active.remove(token);
+
+    true
+  }
+}
\ No newline at end of file
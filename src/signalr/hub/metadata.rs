@@ -1,10 +1,8 @@
-use axum::extract::ws::{self, WebSocket};
-
-use crate::signalr::{hub::{send_json, SignalRProtocol}, message::{CompletionMessage, Message}};
+use crate::{signalr::{connection::SignalRConnection, hub::{send_json, SignalRProtocol}, message::{CompletionMessage, Message}, transport::HubSocket}, state::FiberState};
 
 use super::initiate;
 
-pub async fn handle_metadata_hub(mut socket: WebSocket) {
+pub async fn handle_metadata_hub(state: FiberState, mut socket: HubSocket, connection: SignalRConnection) {
   let protocol = match initiate(&mut socket).await {
     Ok(protocol) => protocol,
     Err(e) => return eprintln!("{:?}", e),
@@ -14,49 +12,46 @@ pub async fn handle_metadata_hub(mut socket: WebSocket) {
     return;
   }
 
-  println!("[metadata] New connection");
-
-  while let Some(Ok(msg)) = socket.recv().await {
-    match msg {
-      ws::Message::Text(data) => {
-        let Ok(msg) = serde_json::from_str::<Message>(&data) else {
-          continue
-        };
-
-        println!("{:?}", msg);
-
-        match msg {
-          Message::Invocation(invocation) => {
-            println!("[metadata] Invoked {}", invocation.target);
-
-            // ...
-
-            if let Some(id) = invocation.invocation_id {
-              let completion = Message::Completion(CompletionMessage {
-                invocation_id: id,
-                result: None,
-                error: None,
-              });
-
-              send_json(&mut socket, completion).await;
-            }
-          },
-          Message::Ping => {
-            println!("[metadata] Ping");
-
-            let ping = Message::Ping;
-
-            send_json(&mut socket, ping).await;
-          },
-          _ => {},
-        }
-      },
-      ws::Message::Close(_frame) => {
-        // ok :(
-      },
-      _ => {
-        // what.
+  println!("[metadata] New connection {} (user {})", connection.id, connection.user_id);
+
+  state.online.connect(connection.user_id);
+
+  while let Some(data) = socket.recv().await {
+    // a single payload can carry several records, each terminated by 0x1e
+    for record in data.split(|b| *b == 0x1E).filter(|r| !r.is_empty()) {
+      let Ok(msg) = serde_json::from_slice::<Message>(record) else {
+        continue
+      };
+
+      println!("{:?}", msg);
+
+      match msg {
+        Message::Invocation(invocation) => {
+          println!("[metadata] Invoked {}", invocation.target);
+
+          // ...
+
+          if let Some(id) = invocation.invocation_id {
+            let completion = Message::Completion(CompletionMessage {
+              invocation_id: id,
+              result: None,
+              error: None,
+            });
+
+            send_json(&socket, completion).await;
+          }
+        },
+        Message::Ping => {
+          println!("[metadata] Ping");
+
+          let ping = Message::Ping;
+
+          send_json(&socket, ping).await;
+        },
+        _ => {},
       }
     }
   }
+
+  state.online.disconnect(connection.user_id);
 }
\ No newline at end of file
@@ -1,15 +1,50 @@
 use std::{error::Error, fmt::Display};
 
 use anyhow::Result;
-use axum::extract::ws::{self, WebSocket};
 use serde::Deserialize;
 
-use super::message::{msgpack::serialize_message, Message};
+use crate::state::FiberState;
+
+use super::{connection::SignalRConnection, message::{msgpack::serialize_message, Message}, transport::HubSocket};
 
 pub mod metadata;
 pub mod multiplayer;
 pub mod spectator;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hub {
+  Metadata,
+  Multiplayer,
+  Spectator,
+}
+
+impl Hub {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "metadata" => Some(Self::Metadata),
+      "multiplayer" => Some(Self::Multiplayer),
+      "spectator" => Some(Self::Spectator),
+      _ => None,
+    }
+  }
+
+  /// The protocol the hub speaks, lazer only uses json for the metadata hub
+  pub fn protocol(self) -> SignalRProtocol {
+    match self {
+      Self::Metadata => SignalRProtocol::Json,
+      Self::Multiplayer | Self::Spectator => SignalRProtocol::Msgpack,
+    }
+  }
+}
+
+pub async fn handle_hub(state: FiberState, hub: Hub, socket: HubSocket, connection: SignalRConnection) {
+  match hub {
+    Hub::Metadata => metadata::handle_metadata_hub(state, socket, connection).await,
+    Hub::Multiplayer => multiplayer::handle_multiplayer_hub(state, socket, connection).await,
+    Hub::Spectator => spectator::handle_spectator_hub(socket, connection).await,
+  }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum SignalRProtocol {
   Msgpack,
@@ -26,6 +61,7 @@ pub struct SignalRHandshake {
 #[derive(Debug)]
 pub enum SignalRHandshakeError {
   UnknownSocketError,
+  InvalidHandshake,
   InvalidProtocol,
 }
 
@@ -37,18 +73,19 @@ impl Display for SignalRHandshakeError {
 
 impl Error for SignalRHandshakeError {}
 
-pub async fn initiate(socket: &mut WebSocket) -> Result<SignalRProtocol> {
-  let Some(msg) = socket.recv().await else {
+pub async fn initiate(socket: &mut HubSocket) -> Result<SignalRProtocol> {
+  let Some(json) = socket.recv().await else {
     return Err(SignalRHandshakeError::UnknownSocketError.into());
   };
-  let msg = msg?;
-
-  let json = msg.into_data();
 
   // trimming 0x1e record separator at the end
-  let handshake = serde_json::from_str::<SignalRHandshake>(str::from_utf8(&json[..json.len() - 1])?)?;
+  let Some(json) = json.strip_suffix(&[0x1e]) else {
+    return Err(SignalRHandshakeError::InvalidHandshake.into());
+  };
+
+  let handshake = serde_json::from_str::<SignalRHandshake>(str::from_utf8(json)?)?;
 
-  socket.send(ws::Message::binary(b"{}\x1E".as_slice())).await?;
+  socket.send(b"{}\x1E".as_slice());
 
   match handshake.protocol.as_str() {
     "messagepack" => Ok(SignalRProtocol::Msgpack),
@@ -57,7 +94,7 @@ pub async fn initiate(socket: &mut WebSocket) -> Result<SignalRProtocol> {
   }
 }
 
-pub async fn send_json(ws: &mut WebSocket, message: Message) {
+pub async fn send_json(socket: &HubSocket, message: Message) {
   let Ok(json) = serde_json::to_string(&message) else {
     return
   };
@@ -65,13 +102,13 @@ pub async fn send_json(ws: &mut WebSocket, message: Message) {
   let mut bytes = json.as_bytes().to_vec();
   bytes.push(0x1E);
 
-  let _ = ws.send(ws::Message::Binary(bytes.into())).await;
+  socket.send(bytes);
 }
 
-pub async fn send_msgpack(ws: &mut WebSocket, message: Message) {
+pub async fn send_msgpack(socket: &HubSocket, message: Message) {
   let Ok(bytes) = serialize_message(&message) else {
     return
   };
 
-  let _ = ws.send(ws::Message::Binary(bytes.into())).await;
+  socket.send(bytes);
 }
\ No newline at end of file
@@ -1,10 +1,54 @@
-use axum::extract::ws::{self, WebSocket};
+use serde_json::json;
 
-use crate::signalr::{hub::send_msgpack, message::{msgpack::deserialize_message, CompletionMessage, Message}};
+use crate::{auth::User, notifications::{self, NewNotification}, relations, signalr::{connection::SignalRConnection, hub::send_msgpack, message::{msgpack::deserialize_message, CompletionMessage, InvocationMessage, Message}, transport::HubSocket, value::SignalRValue}, state::FiberState};
 
 use super::{initiate, SignalRProtocol};
 
-pub async fn handle_multiplayer_hub(mut socket: WebSocket) {
+/// Invites a user to the inviter's room and notifies them, refusing users that blocked each other or only talk to friends
+/// the way osu-server-spectator does
+async fn invite_player(state: &FiberState, connection: &SignalRConnection, invocation: &InvocationMessage) -> Option<String> {
+  let Some(SignalRValue::Integer(target_id)) = invocation.arguments.first() else {
+    return Some("Invalid arguments.".into());
+  };
+
+  let Some(room_id) = state.rooms.room_of(connection.user_id) else {
+    return Some("User is not in a room.".into());
+  };
+
+  match relations::can_invite(&state.pool, connection.user_id, *target_id).await {
+    Ok(Ok(())) => {},
+    Ok(Err(restriction)) => return Some(restriction.message().into()),
+    Err(e) => {
+      eprintln!("failed to look up relations: {:?}", e);
+      return Some("Failed to invite player.".into());
+    },
+  }
+
+  let inviter = sqlx::query_as::<_, User>("select * from users where id = ?")
+    .bind(connection.user_id)
+    .fetch_optional(&state.pool)
+    .await;
+
+  let Ok(Some(inviter)) = inviter else {
+    return Some("Failed to invite player.".into());
+  };
+
+  let notification = NewNotification {
+    name: "multiplayer_invite",
+    object_type: "room",
+    object_id: room_id,
+    source_user_id: Some(inviter.id),
+    details: json!({ "username": inviter.username, "cover_url": inviter.avatar_url(&state.config) }),
+  };
+
+  if let Err(e) = notifications::notify(state, *target_id, notification).await {
+    eprintln!("failed to send notification: {:?}", e);
+  }
+
+  None
+}
+
+pub async fn handle_multiplayer_hub(state: FiberState, mut socket: HubSocket, connection: SignalRConnection) {
   let protocol = match initiate(&mut socket).await {
     Ok(protocol) => protocol,
     Err(e) => return eprintln!("{:?}", e),
@@ -14,47 +58,48 @@ pub async fn handle_multiplayer_hub(mut socket: WebSocket) {
     return;
   }
 
-  println!("[spectator] New connection");
-
-  while let Some(Ok(msg)) = socket.recv().await {
-    match msg {
-      ws::Message::Binary(data) => {
-        let Ok(msg) = deserialize_message(&data) else {
-          continue
-        };
-
-        match msg {
-          Message::Invocation(invocation) => {
-            println!("[multiplayer] Invoked {}", invocation.target);
+  println!("[multiplayer] New connection {} (user {})", connection.id, connection.user_id);
 
-            // ...
+  while let Some(data) = socket.recv().await {
+    let Ok(msg) = deserialize_message(&data) else {
+      continue
+    };
 
-            if let Some(id) = invocation.invocation_id {
-              let completion = Message::Completion(CompletionMessage {
-                invocation_id: id,
-                result: None,
-                error: None,
-              });
+    match msg {
+      Message::Invocation(invocation) => {
+        println!("[multiplayer] Invoked {}", invocation.target);
 
-              send_msgpack(&mut socket, completion).await;
-            }
+        let error = match invocation.target.as_str() {
+          "InvitePlayer" => invite_player(&state, &connection, &invocation).await,
+          "LeaveRoom" => {
+            state.rooms.leave(connection.user_id);
+            None
           },
-          Message::Ping => {
-            println!("[multiplayer] Ping");
+          // ...
+          _ => None,
+        };
 
-            let ping = Message::Ping;
+        if let Some(id) = invocation.invocation_id {
+          let completion = Message::Completion(CompletionMessage {
+            invocation_id: id,
+            result: None,
+            error,
+          });
 
-            send_msgpack(&mut socket, ping).await;
-          },
-          _ => {},
+          send_msgpack(&socket, completion).await;
         }
       },
-      ws::Message::Close(_frame) => {
-        // ok :(
+      Message::Ping => {
+        println!("[multiplayer] Ping");
+
+        let ping = Message::Ping;
+
+        send_msgpack(&socket, ping).await;
       },
-      _ => {
-        // what.
-      }
+      _ => {},
     }
   }
+
+  // the room is left with the connection
+  state.rooms.leave(connection.user_id);
 }
\ No newline at end of file
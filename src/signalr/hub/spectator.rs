@@ -1,10 +1,8 @@
-use axum::extract::ws::{self, WebSocket};
-
-use crate::signalr::{hub::send_msgpack, message::{msgpack::deserialize_message, CompletionMessage, Message}};
+use crate::signalr::{connection::SignalRConnection, hub::send_msgpack, message::{msgpack::deserialize_message, CompletionMessage, Message}, transport::HubSocket};
 
 use super::{initiate, SignalRProtocol};
 
-pub async fn handle_spectator_hub(mut socket: WebSocket) {
+pub async fn handle_spectator_hub(mut socket: HubSocket, connection: SignalRConnection) {
   let protocol = match initiate(&mut socket).await {
     Ok(protocol) => protocol,
     Err(e) => return eprintln!("{:?}", e),
@@ -14,54 +12,44 @@ pub async fn handle_spectator_hub(mut socket: WebSocket) {
     return;
   }
 
-  println!("[spectator] New connection");
+  println!("[spectator] New connection {} (user {})", connection.id, connection.user_id);
+
+  while let Some(data) = socket.recv().await {
+    // let Ok(msg) = deserialize_message(&data) else {
+    //   continue
+    // };
+    let msg = match deserialize_message(&data) {
+      Ok(msg) => msg,
+      Err(e) => {
+        eprintln!("{}", e.backtrace());
+        continue
+      }
+    };
 
-  while let Some(Ok(msg)) = socket.recv().await {
     match msg {
-      ws::Message::Binary(data) => {
-        // let Ok(msg) = deserialize_message(&data) else {
-        //   continue
-        // };
-        let msg = match deserialize_message(&data) {
-          Ok(msg) => msg,
-          Err(e) => {
-            eprintln!("{}", e.backtrace());
-            continue
-          }
-        };
-
-        match msg {
-          Message::Invocation(invocation) => {
-            println!("[spectator] Invoked {} with {:?}", invocation.target, invocation.arguments);
-
-            // ...
+      Message::Invocation(invocation) => {
+        println!("[spectator] Invoked {} with {:?}", invocation.target, invocation.arguments);
 
-            if let Some(id) = invocation.invocation_id {
-              let completion = Message::Completion(CompletionMessage {
-                invocation_id: id,
-                result: None,
-                error: None,
-              });
+        // ...
 
-              send_msgpack(&mut socket, completion).await;
-            }
-          },
-          Message::Ping => {
-            println!("[spectator] Ping");
+        if let Some(id) = invocation.invocation_id {
+          let completion = Message::Completion(CompletionMessage {
+            invocation_id: id,
+            result: None,
+            error: None,
+          });
 
-            let ping = Message::Ping;
-
-            send_msgpack(&mut socket, ping).await;
-          },
-          _ => {},
+          send_msgpack(&socket, completion).await;
         }
       },
-      ws::Message::Close(_frame) => {
-        // ok :(
+      Message::Ping => {
+        println!("[spectator] Ping");
+
+        let ping = Message::Ping;
+
+        send_msgpack(&socket, ping).await;
       },
-      _ => {
-        // what.
-      }
+      _ => {},
     }
   }
 }
\ No newline at end of file
@@ -62,11 +62,11 @@ pub fn deserialize_message(buf: &[u8]) -> Result<Message> {
         },
         target: data.remove(0)
           .as_str()
-          .ok_or_else(|| MsgpackParseError::InvalidType)?
+          .ok_or(MsgpackParseError::InvalidType)?
           .into(),
         arguments: data.remove(0)
           .as_array()
-          .ok_or_else(|| MsgpackParseError::InvalidType)?
+          .ok_or(MsgpackParseError::InvalidType)?
           .iter()
           .map(|v| v.into())
           .collect()
@@ -1,3 +1,5 @@
+pub mod connection;
 pub mod hub;
 pub mod message;
+pub mod transport;
 pub mod value;
\ No newline at end of file
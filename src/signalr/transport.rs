@@ -0,0 +1,76 @@
+use axum::{body::Bytes, extract::ws::{self, WebSocket}};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transport {
+  WebSockets,
+  ServerSentEvents,
+  LongPolling,
+}
+
+/// Hub side of a connection.
+///
+/// Hubs only see raw payloads, so the same hub can run over any transport.
+pub struct HubSocket {
+  incoming: UnboundedReceiver<Bytes>,
+  outgoing: UnboundedSender<Bytes>,
+}
+
+/// Transport side of a connection
+pub struct TransportSocket {
+  pub incoming: UnboundedSender<Bytes>,
+  pub outgoing: UnboundedReceiver<Bytes>,
+}
+
+impl HubSocket {
+  pub fn pair() -> (HubSocket, TransportSocket) {
+    let (incoming_tx, incoming_rx) = mpsc::unbounded_channel();
+    let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel();
+
+    (
+      HubSocket {
+        incoming: incoming_rx,
+        outgoing: outgoing_tx,
+      },
+      TransportSocket {
+        incoming: incoming_tx,
+        outgoing: outgoing_rx,
+      },
+    )
+  }
+
+  /// Returns the next payload sent by the client, or `None` once the transport is gone
+  pub async fn recv(&mut self) -> Option<Bytes> {
+    self.incoming.recv().await
+  }
+
+  pub fn send(&self, data: impl Into<Bytes>) {
+    let _ = self.outgoing.send(data.into());
+  }
+}
+
+/// Pumps frames between a websocket and the hub until either side goes away
+pub async fn pump_websocket(mut ws: WebSocket, mut transport: TransportSocket) {
+  loop {
+    tokio::select! {
+      msg = ws.recv() => match msg {
+        Some(Ok(ws::Message::Text(data))) => {
+          let _ = transport.incoming.send(Bytes::from(data));
+        },
+        Some(Ok(ws::Message::Binary(data))) => {
+          let _ = transport.incoming.send(data);
+        },
+        Some(Ok(ws::Message::Close(_))) | Some(Err(_)) | None => break,
+        Some(Ok(_)) => {},
+      },
+      data = transport.outgoing.recv() => match data {
+        Some(data) => {
+          if ws.send(ws::Message::Binary(data)).await.is_err() {
+            break;
+          }
+        },
+        None => break,
+      },
+    }
+  }
+}
\ No newline at end of file
@@ -1,8 +1,8 @@
 use super::SignalRValue;
 
-impl Into<serde_json::Value> for &SignalRValue {
-  fn into(self) -> serde_json::Value {
-    match self {
+impl From<&SignalRValue> for serde_json::Value {
+  fn from(value: &SignalRValue) -> Self {
+    match value {
       SignalRValue::Integer(n) => serde_json::Value::Number(serde_json::Number::from_i128(*n as i128).unwrap()),
       SignalRValue::Float(f) => serde_json::Value::Number(serde_json::Number::from_f64(*f).unwrap()),
       SignalRValue::String(s) => serde_json::Value::String(s.clone()),
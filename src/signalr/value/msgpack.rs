@@ -1,8 +1,8 @@
 use super::SignalRValue;
 
-impl Into<rmpv::Value> for &SignalRValue {
-  fn into(self) -> rmpv::Value {
-    match self {
+impl From<&SignalRValue> for rmpv::Value {
+  fn from(value: &SignalRValue) -> Self {
+    match value {
       SignalRValue::Integer(n) => rmpv::Value::Integer(rmpv::Integer::from(*n)),
       SignalRValue::Float(f) => rmpv::Value::F64(*f),
       SignalRValue::String(s) => rmpv::Value::String(s.clone().into()),
@@ -1,18 +1,44 @@
-use std::sync::Arc;
+use std::{str::FromStr, sync::Arc};
 
 use anyhow::Result;
-use sqlx::{Pool, Sqlite, SqlitePool};
+use sqlx::{sqlite::SqliteConnectOptions, Pool, Sqlite, SqlitePool};
+
+use crate::{chat::RateLimiter, config::Config, notifications::NotificationConnections, presence::OnlineUsers, rooms::RoomMembers, scores::replay::ReplayStore, signalr::connection::SignalRConnections, storage::Storage};
 
 pub type FiberState = Arc<FiberStateInner>;
 
 pub struct FiberStateInner {
   pub pool: Pool<Sqlite>,
+  pub config: Config,
+  pub signalr: SignalRConnections,
+  pub notifications: NotificationConnections,
+  pub online: OnlineUsers,
+  pub rooms: RoomMembers,
+  pub chat_limits: RateLimiter,
+  pub storage: Storage,
+  /// avatars and covers, kept apart from beatmap files since they're served to anyone
+  pub images: Storage,
+  pub replays: ReplayStore,
 }
 
 impl FiberStateInner {
   pub async fn new() -> Result<Self> {
+    let config = Config::from_env();
+
+    let options = SqliteConnectOptions::from_str(&config.database_url)?
+      .create_if_missing(true);
+
     Ok(Self {
-      pool: SqlitePool::connect("sqlite:fibers.db").await?
+      pool: SqlitePool::connect_with(options).await?,
+      storage: Storage::new(config.data_dir.join("files")),
+      images: Storage::new(config.data_dir.join("images")),
+      replays: ReplayStore::new(config.data_dir.join("replays")),
+      config,
+      signalr: SignalRConnections::default(),
+      notifications: NotificationConnections::default(),
+      online: OnlineUsers::default(),
+      rooms: RoomMembers::default(),
+      chat_limits: RateLimiter::default(),
     })
   }
 }
\ No newline at end of file
@@ -0,0 +1,48 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use tokio::fs;
+
+/// Content-addressed file storage, files are stored under the sha256 of their contents
+pub struct Storage {
+  root: PathBuf,
+}
+
+pub fn hash(data: &[u8]) -> String {
+  hex::encode(Sha256::digest(data))
+}
+
+impl Storage {
+  pub fn new(root: PathBuf) -> Self {
+    Self { root }
+  }
+
+  pub fn path(&self, hash: &str) -> PathBuf {
+    self.root
+      .join(&hash[..2])
+      .join(&hash[2..4])
+      .join(hash)
+  }
+
+  /// Stores `data`, returning its hash. Files that are already stored aren't written again.
+  pub async fn put(&self, data: &[u8]) -> Result<String> {
+    let hash = hash(data);
+    let path = self.path(&hash);
+
+    if !fs::try_exists(&path).await? {
+      fs::create_dir_all(path.parent().unwrap()).await?;
+
+      // write to a temporary file first so a crash can't leave a truncated file behind
+      let tmp = path.with_extension("tmp");
+      fs::write(&tmp, data).await?;
+      fs::rename(&tmp, &path).await?;
+    }
+
+    Ok(hash)
+  }
+
+  pub async fn get(&self, hash: &str) -> Result<Vec<u8>> {
+    Ok(fs::read(self.path(hash)).await?)
+  }
+}
\ No newline at end of file